@@ -0,0 +1,70 @@
+#![no_main]
+use std::io::{self, Read};
+
+use libfuzzer_sys::fuzz_target;
+use yatta_core::{MessageReader, WireFormat, MAX_FRAME_LEN};
+
+/// Hands out `data` at most `chunk` bytes at a time, like a socket that
+/// receives a message over several reads
+struct Chunked<'a> {
+    data:  &'a [u8],
+    chunk: usize,
+}
+
+impl Read for Chunked<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.chunk.min(buf.len()).min(self.data.len());
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        Ok(n)
+    }
+}
+
+fn messages<R: Read>(reader: R, format: WireFormat) -> Vec<io::Result<Vec<u8>>> {
+    let mut reader = MessageReader::new(reader);
+    reader.format = format;
+
+    std::iter::from_fn(|| reader.read_frame().transpose()).collect()
+}
+
+// The first byte picks the wire format and how the rest of the input is split
+// up between reads, which shouldn't change the messages that come out the
+// other end. Messages are never longer than MAX_FRAME_LEN, which the second
+// byte can put the input over.
+fuzz_target!(|data: &[u8]| {
+    let (first, data) = match data.split_first() {
+        Some((first, data)) => (*first, data),
+        None => return,
+    };
+
+    let format = match first % 2 {
+        0 => WireFormat::Json,
+        _ => WireFormat::MessagePack,
+    };
+    let chunk = usize::from(first / 2).max(1);
+
+    let long;
+    let data = match data.split_first() {
+        Some((second, rest)) if second % 2 == 1 => {
+            long = [&[b'x'; MAX_FRAME_LEN][..], rest].concat();
+            &long[..]
+        }
+        _ => data,
+    };
+
+    let whole = messages(data, format);
+    let chunked = messages(Chunked { data, chunk }, format);
+
+    for frame in whole.iter().flatten() {
+        assert!(frame.len() <= MAX_FRAME_LEN);
+    }
+
+    assert_eq!(whole.len(), chunked.len());
+    for (whole, chunked) in whole.iter().zip(&chunked) {
+        match (whole, chunked) {
+            (Ok(whole), Ok(chunked)) => assert_eq!(whole, chunked),
+            (Err(whole), Err(chunked)) => assert_eq!(whole.kind(), chunked.kind()),
+            _ => panic!("{:?} != {:?}", whole, chunked),
+        }
+    }
+});
@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use yatta_core::{SocketMessage, WireFormat};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = SocketMessage::from_bytes(data);
+    let _ = WireFormat::MessagePack.decode::<SocketMessage>(data);
+});
@@ -1,31 +1,219 @@
 use anyhow::Result;
 use clap::Clap;
-use serde::{Deserialize, Serialize};
-use std::str::FromStr;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    convert::TryFrom,
+    io::{self, BufRead, BufReader, ErrorKind, Read},
+    str::FromStr,
+};
 use strum::{Display, EnumString};
 
+pub mod state;
+
+/// With the JSON wire format every message sent over the socket, in either
+/// direction, is a single line of JSON ended by this delimiter, so that any
+/// number of messages can be sent over one connection. JSON never contains a
+/// raw newline, so the delimiter can't turn up in the middle of a message.
+pub const MESSAGE_DELIMITER: u8 = b'\n';
+
+/// The longest message that a `MessageReader` reads, in bytes and without its
+/// framing. Anything longer is an error rather than being buffered, so that a
+/// client can't make yatta hold on to an endless message.
+pub const MAX_FRAME_LEN: usize = 1024 * 1024;
+
 #[derive(Clone, Debug, Serialize, Deserialize, Display)]
 pub enum SocketMessage {
-    AdjustGaps(Sizing),
+    /// Grows or shrinks the inner gap by a number of pixels
+    AdjustInnerGap(Sizing, i32),
+    /// Grows or shrinks the outer gap by a number of pixels
+    AdjustOuterGap(Sizing, i32),
     FocusWindow(OperationDirection),
+    /// Cycles focus through the floating windows on the display
+    FocusFloat(CycleDirection),
     MoveWindow(OperationDirection),
+    /// Swaps the focused window with the nearest window in a direction that
+    /// is floating if it is tiled, or tiled if it is floating
+    SwapWithFloat(OperationDirection),
     ResizeWindow(ResizeEdge, Sizing),
     MoveWindowToDisplay(CycleDirection),
     MoveWindowToDisplayNumber(usize),
     FocusDisplay(CycleDirection),
     FocusDisplayNumber(usize),
     Promote,
-    Retile,
-    Layout(Layout),
-    CycleLayout(CycleDirection),
-    GapSize(i32),
-    PaddingSize(i32),
+    /// Holds back moving windows until EndTransaction, so that a series of
+    /// commands only moves windows once
+    BeginTransaction,
+    EndTransaction,
+    Retile(Option<usize>),
+    Layout(Layout, Option<usize>),
+    CycleLayout(CycleDirection, Option<usize>),
+    SetInnerGap(i32, Option<usize>),
+    SetOuterGap(i32, Option<usize>),
+    SetColumns(usize, Option<usize>),
+    SetRows(usize, Option<usize>),
     ToggleFloat,
+    /// Floats every window on the display, remembering which were tiled
+    FloatAll,
+    /// Tiles the windows that were tiled before FloatAll
+    TileAll,
     TogglePause,
     ToggleMonocle,
+    /// Draws the focused window bigger than its tile, over its neighbours
+    ToggleZoomFocused,
+    /// Keeps the focused window in the first position of its display
+    ToggleMaster,
+    SetFloat(bool),
+    SetPause(bool),
+    SetMonocle(bool),
+    Query(Query),
     FloatClass(String),
     FloatExe(String),
     FloatTitle(String),
+    /// Focuses the managed window with this hwnd, wherever it is
+    FocusWindowByHwnd(u64),
+    /// Floats or tiles the managed window with this hwnd
+    FloatWindowByHwnd(u64, bool),
+    /// Moves the managed window with this hwnd to a display number
+    MoveHwndToDisplayNumber(u64, usize),
+    /// Adds to the rules yatta is running with
+    ImportRules(Rules),
+    /// Brings yatta in line with a description of how it should be set up
+    ApplyState(state::DesiredState),
+    /// Swaps a window that was in the focused window's position before back
+    /// into it
+    CycleTileOccupants(CycleDirection),
+    /// Puts back every window yatta has hidden or zoomed and exits
+    Stop,
+    /// Puts back every window yatta has hidden or zoomed and stops managing
+    /// windows until resumed, without exiting
+    Suspend,
+    /// Manages and tiles whatever windows are open after a suspend
+    Resume,
+    /// Switches the connection to another wire format. yatta replies with the
+    /// name of the format, still in the old one, and uses the new format for
+    /// everything after that.
+    Handshake(WireFormat),
+    /// Presents the token yatta was started with `--require-token` to write
+    /// next to its socket. yatta replies if the token is right and closes the
+    /// connection if it isn't.
+    Authenticate(String),
+    /// Turns the connection into a stream of `state::Event`s, each a line of
+    /// JSON, for as long as the client stays connected
+    Subscribe,
+}
+
+/// How messages are encoded on the socket. Every connection starts out with
+/// JSON, which is easy to read when debugging, and can switch to MessagePack
+/// with a `SocketMessage::Handshake` to save on encoding lots of messages.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Display, EnumString)]
+#[strum(serialize_all = "snake_case")]
+#[derive(Clap)]
+pub enum WireFormat {
+    /// Lines of JSON ended by `MESSAGE_DELIMITER`
+    Json,
+    /// MessagePack, with each message prefixed by its length as a big endian
+    /// u32, which can't be more than `MAX_FRAME_LEN`
+    MessagePack,
+}
+
+impl WireFormat {
+    /// Encodes `value` and frames it so that it can be written straight to the
+    /// socket
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            WireFormat::Json => {
+                let mut bytes = serde_json::to_vec(value)?;
+                bytes.push(MESSAGE_DELIMITER);
+                Ok(bytes)
+            }
+            WireFormat::MessagePack => {
+                let payload = rmp_serde::to_vec(value)?;
+                let mut bytes = u32::try_from(payload.len())?.to_be_bytes().to_vec();
+                bytes.extend(payload);
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Decodes a single message read by a `MessageReader`, JSON can have its
+    /// delimiter left on
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T> {
+        match self {
+            WireFormat::Json => Ok(serde_json::from_slice(bytes)?),
+            WireFormat::MessagePack => Ok(rmp_serde::from_slice(bytes)?),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Display, EnumString)]
+#[strum(serialize_all = "snake_case")]
+#[derive(Clap)]
+pub enum Query {
+    /// Pause, layout, monocle and float state of the displays and windows
+    State,
+    /// Layout, gaps and windows of each display, in display number order
+    Displays,
+    /// Every managed window with its hwnd, title, exe and display
+    Windows,
+    /// The focused window of the active display, or null if there is none
+    Focused,
+    /// The settings yatta is running with, written as a config file
+    Config,
+    /// How long handling each kind of windows event and yattac message takes
+    Metrics,
+    /// The float and manage rules, in the format read by `yattac rules import`
+    Rules,
+    /// How many windows of each exe or window class yatta is managing, to
+    /// help with writing rules
+    Managed(Managed),
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, Clap)]
+pub struct Managed {
+    #[clap(long, default_value = "exe")]
+    pub group_by: GroupBy,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Display, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum GroupBy {
+    Exe,
+    Class,
+}
+
+// Deriving this needs #[default] on the variant, which needs Rust 1.62
+#[allow(clippy::derivable_impls)]
+impl Default for GroupBy {
+    fn default() -> Self {
+        GroupBy::Exe
+    }
+}
+
+/// The rules yatta uses to decide which windows to float and how to manage
+/// windows of particular exes, which can be exported and imported as a set
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rules {
+    pub float_classes:              Vec<String>,
+    pub float_exes:                 Vec<String>,
+    pub float_titles:               Vec<String>,
+    pub float_windows:              Vec<WindowRule>,
+    pub splash_exes:                Vec<String>,
+    pub name_change_on_launch_exes: Vec<String>,
+}
+
+/// Matches windows that have every property given, for rules that keep working
+/// when an app is translated. The exe is compared without regard to case, and
+/// the title is a regular expression that ignores case.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowRule {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exe:   Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, Display, EnumString)]
@@ -40,7 +228,7 @@ pub enum OperationDirection {
     Next,
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, Display, EnumString)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Display, EnumString)]
 #[strum(serialize_all = "snake_case")]
 #[derive(Clap)]
 pub enum Layout {
@@ -48,6 +236,9 @@ pub enum Layout {
     BSPH,
     Columns,
     Rows,
+    /// The first window centred with the others stacked in the space either
+    /// side of it
+    Centred,
     Monocle,
 }
 
@@ -57,7 +248,8 @@ impl Layout {
             Layout::BSPV => *self = Layout::BSPH,
             Layout::BSPH => *self = Layout::Columns,
             Layout::Columns => *self = Layout::Rows,
-            Layout::Rows => *self = Layout::Monocle,
+            Layout::Rows => *self = Layout::Centred,
+            Layout::Centred => *self = Layout::Monocle,
             Layout::Monocle => *self = Layout::BSPV,
         }
     }
@@ -68,7 +260,8 @@ impl Layout {
             Layout::BSPH => *self = Layout::BSPV,
             Layout::Columns => *self = Layout::BSPH,
             Layout::Rows => *self = Layout::Columns,
-            Layout::Monocle => *self = Layout::Rows,
+            Layout::Centred => *self = Layout::Rows,
+            Layout::Monocle => *self = Layout::Centred,
         }
     }
 }
@@ -100,12 +293,77 @@ pub enum ResizeEdge {
 }
 
 impl SocketMessage {
+    /// The display number (starting from 1) explicitly targeted by this
+    /// message, if any. Messages without a target act on the display under
+    /// the cursor.
+    pub fn display(&self) -> Option<usize> {
+        match self {
+            SocketMessage::Retile(display)
+            | SocketMessage::Layout(_, display)
+            | SocketMessage::CycleLayout(_, display)
+            | SocketMessage::SetInnerGap(_, display)
+            | SocketMessage::SetOuterGap(_, display)
+            | SocketMessage::SetColumns(_, display)
+            | SocketMessage::SetRows(_, display) => *display,
+            _ => None,
+        }
+    }
+
+    /// The hwnd of the window explicitly targeted by this message, if any.
+    /// Messages without a target act on the foreground window.
+    pub fn hwnd(&self) -> Option<u64> {
+        match self {
+            SocketMessage::FocusWindowByHwnd(hwnd)
+            | SocketMessage::FloatWindowByHwnd(hwnd, _)
+            | SocketMessage::MoveHwndToDisplayNumber(hwnd, _) => Option::from(*hwnd),
+            _ => None,
+        }
+    }
+
+    /// Whether sending this message again straight after it was handled has no
+    /// further effect, so that yatta can drop identical repeats
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self,
+            SocketMessage::Retile(_)
+                | SocketMessage::Layout(..)
+                | SocketMessage::SetInnerGap(..)
+                | SocketMessage::SetOuterGap(..)
+                | SocketMessage::SetColumns(..)
+                | SocketMessage::SetRows(..)
+                | SocketMessage::FloatAll
+                | SocketMessage::TileAll
+                | SocketMessage::SetFloat(_)
+                | SocketMessage::SetPause(_)
+                | SocketMessage::SetMonocle(_)
+                | SocketMessage::FloatClass(_)
+                | SocketMessage::FloatExe(_)
+                | SocketMessage::FloatTitle(_)
+                | SocketMessage::FloatWindowByHwnd(..)
+                | SocketMessage::MoveHwndToDisplayNumber(..)
+                | SocketMessage::FocusDisplayNumber(_)
+                | SocketMessage::ImportRules(_)
+                | SocketMessage::ApplyState(_)
+        )
+    }
+
+    /// Whether yatta writes a response back to the connection this message came
+    /// from
+    pub fn has_response(&self) -> bool {
+        matches!(
+            self,
+            SocketMessage::Query(_) | SocketMessage::Handshake(_) | SocketMessage::Authenticate(_)
+        )
+    }
+
+    /// The message as JSON, framed with a trailing `MESSAGE_DELIMITER`
     pub fn as_bytes(&self) -> Result<Vec<u8>> {
-        Ok(serde_json::to_string(self)?.as_bytes().to_vec())
+        WireFormat::Json.encode(self)
     }
 
+    /// Parses a single JSON message, with or without its delimiter
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        Ok(serde_json::from_slice(bytes)?)
+        WireFormat::Json.decode(bytes)
     }
 }
 
@@ -116,3 +374,169 @@ impl FromStr for SocketMessage {
         serde_json::from_str(s)
     }
 }
+
+/// Reads framed messages from `reader` in `format`, however the bytes happen
+/// to be split up between reads. As an iterator it pairs each message with the
+/// result of parsing it as a `SocketMessage`, where the message is lossily
+/// converted to a string for logging. A read timeout is returned as an error,
+/// unless the reader is set to `wait_while_idle`.
+pub struct MessageReader<R> {
+    reader:          BufReader<R>,
+    pub format:      WireFormat,
+    wait_while_idle: bool,
+}
+
+impl<R: Read> MessageReader<R> {
+    pub fn new(reader: R) -> Self {
+        MessageReader {
+            reader:          BufReader::new(reader),
+            format:          WireFormat::Json,
+            wait_while_idle: false,
+        }
+    }
+
+    /// Keeps waiting through read timeouts that happen between messages, for
+    /// connections where the other side can be idle for a long time. A
+    /// timeout partway through a message is still an error.
+    pub fn wait_while_idle(mut self) -> Self {
+        self.wait_while_idle = true;
+        self
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        self.reader.get_mut()
+    }
+
+    /// The next message without its framing, or None once the other side has
+    /// closed the connection
+    pub fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut bytes = vec![];
+
+        match self.format {
+            WireFormat::Json => {
+                if !self.read_line(&mut bytes)? {
+                    return Ok(None);
+                }
+
+                if bytes.last() == Some(&MESSAGE_DELIMITER) {
+                    bytes.pop();
+                }
+
+                if bytes.last() == Some(&b'\r') {
+                    bytes.pop();
+                }
+            }
+            WireFormat::MessagePack => {
+                if !self.read_exact(&mut bytes, 4)? {
+                    return Ok(None);
+                }
+
+                let len = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+                bytes.clear();
+
+                if len > MAX_FRAME_LEN {
+                    return Err(frame_too_long());
+                }
+
+                if len > 0 && !self.read_exact(&mut bytes, len)? {
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+            }
+        }
+
+        Ok(Option::from(bytes))
+    }
+
+    /// Reads up to and including the next delimiter, returning false if the
+    /// stream ended before anything was read. Stops with an error once more
+    /// than `MAX_FRAME_LEN` bytes have come without a delimiter.
+    fn read_line(&mut self, bytes: &mut Vec<u8>) -> io::Result<bool> {
+        loop {
+            let available = match self.reader.fill_buf() {
+                Ok(available) => available,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) if self.wait_while_idle && is_idle_timeout(bytes, &error) => continue,
+                Err(error) => return Err(error),
+            };
+
+            if available.is_empty() {
+                return Ok(!bytes.is_empty());
+            }
+
+            // Never takes more than the limit, so that the same bytes are left
+            // however the message was split up between reads
+            let room = MAX_FRAME_LEN - bytes.len();
+            let (n, done) = match available.iter().position(|b| *b == MESSAGE_DELIMITER) {
+                Some(idx) if idx <= room => (idx + 1, true),
+                _ if room == 0 => return Err(frame_too_long()),
+                _ => (available.len().min(room), false),
+            };
+
+            bytes.extend_from_slice(&available[..n]);
+            self.reader.consume(n);
+
+            if done {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Reads exactly `len` bytes, returning false if the stream ended before
+    /// anything was read
+    fn read_exact(&mut self, bytes: &mut Vec<u8>, len: usize) -> io::Result<bool> {
+        while bytes.len() < len {
+            let available = match self.reader.fill_buf() {
+                Ok(available) => available,
+                Err(error) if self.wait_while_idle && is_idle_timeout(bytes, &error) => continue,
+                Err(error) => return Err(error),
+            };
+
+            if available.is_empty() {
+                if bytes.is_empty() {
+                    return Ok(false);
+                }
+
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+
+            let n = available.len().min(len - bytes.len());
+            bytes.extend_from_slice(&available[..n]);
+            self.reader.consume(n);
+        }
+
+        Ok(true)
+    }
+}
+
+impl<R: Read> Iterator for MessageReader<R> {
+    type Item = io::Result<(String, Result<SocketMessage>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = match self.read_frame() {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => return None,
+            Err(error) => return Some(Err(error)),
+        };
+
+        let message = String::from_utf8_lossy(&bytes).to_string();
+        Some(Ok((message, self.format.decode(&bytes))))
+    }
+}
+
+/// Reads JSON messages from `reader`, see `MessageReader`
+pub fn read_messages<R: Read>(reader: R) -> MessageReader<R> {
+    MessageReader::new(reader)
+}
+
+fn frame_too_long() -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("message is longer than {} bytes", MAX_FRAME_LEN),
+    )
+}
+
+/// Whether `error` is a read timing out between messages rather than partway
+/// through one
+fn is_idle_timeout(bytes: &[u8], error: &io::Error) -> bool {
+    bytes.is_empty() && matches!(error.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock)
+}
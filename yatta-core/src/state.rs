@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Layout, Rules};
+
+/// Bumped whenever a change to these types would break clients reading them
+pub const STATE_VERSION: u32 = 1;
+
+/// Snapshot of the desktop returned by `yattac query state`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    /// The `STATE_VERSION` of the yatta that took the snapshot
+    pub version:  u32,
+    pub paused:   bool,
+    pub displays: Vec<DisplayState>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DisplayState {
+    /// The area windows are tiled in, inside the outer gap
+    pub area:      Rect,
+    pub layout:    Layout,
+    pub monocle:   bool,
+    pub inner_gap: i32,
+    pub outer_gap: i32,
+    pub windows:   Vec<WindowState>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WindowState {
+    /// Can be given to DwmRegisterThumbnail to show a live preview of the
+    /// window
+    pub hwnd:      u64,
+    pub title:     Option<String>,
+    pub exe:       String,
+    pub rect:      Rect,
+    pub floating:  bool,
+    pub focused:   bool,
+    /// yatta has given up on moving the window, so it is floating
+    pub unmovable: bool,
+    /// Hidden or minimized by yatta in the Monocle layout. DWM has nothing to
+    /// draw a live preview of for a hidden window.
+    #[serde(default)]
+    pub hidden:    bool,
+    /// Number of the display the window is on, counting from 1 like
+    /// `yattac focus-display-number`
+    #[serde(default)]
+    pub display:   usize,
+}
+
+/// One line of `yattac query managed`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManagedGroup {
+    /// The exe or window class, depending on what was asked for
+    pub name:     String,
+    pub windows:  usize,
+    /// How many of the windows are floating
+    pub floating: usize,
+}
+
+/// Sent to `yattac subscribe` connections whenever something a status bar
+/// might show changes. Displays are numbered from 1. A window moving to
+/// another display is sent as it being unmanaged on one and managed on the
+/// other.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Paused {
+        paused: bool,
+    },
+    /// Another display became the active one
+    DisplayFocused {
+        display: usize,
+    },
+    LayoutChanged {
+        display: usize,
+        layout:  Layout,
+    },
+    WindowFocused {
+        display: usize,
+        hwnd:    u64,
+        title:   Option<String>,
+    },
+    WindowManaged {
+        display: usize,
+        hwnd:    u64,
+        title:   Option<String>,
+        exe:     String,
+    },
+    WindowUnmanaged {
+        display: usize,
+        hwnd:    u64,
+    },
+}
+
+/// How yatta should be set up, sent with `yattac apply-state`. Anything left
+/// out is left as it is, and applying the same state twice changes nothing
+/// the second time.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DesiredState {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paused:   Option<bool>,
+    /// Replaces the rules yatta is running with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rules:    Option<Rules>,
+    /// In display number order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub displays: Vec<DesiredDisplay>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DesiredDisplay {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout:    Option<Layout>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inner_gap: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outer_gap: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub columns:   Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rows:      Option<usize>,
+}
+
+/// x & y coordinates are relative to top left of screen
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rect {
+    pub x:      i32,
+    pub y:      i32,
+    pub width:  i32,
+    pub height: i32,
+}
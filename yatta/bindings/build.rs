@@ -1,23 +1,57 @@
 fn main() {
     windows::build!(
         Windows::Win32::Foundation::{
+            CloseHandle,
+            HANDLE,
             POINT,
             RECT,
             BOOL,
             PWSTR,
             HWND,
             LPARAM,
+            SYSTEMTIME,
         },
         Windows::Win32::Graphics::Dwm::*,
         Windows::Win32::Graphics::Gdi::*,
+        Windows::Win32::System::Com::{
+            CoCreateInstance,
+            CoInitializeEx,
+            CLSCTX_INPROC_SERVER,
+            COINIT_APARTMENTTHREADED,
+            COINIT_MULTITHREADED,
+        },
+        Windows::Win32::System::LibraryLoader::GetModuleHandleW,
+        Windows::Win32::System::OleAutomation::{VARIANT, VT_I4},
+        Windows::Win32::System::SystemInformation::GetLocalTime,
         Windows::Win32::System::Threading::{
             PROCESS_ACCESS_RIGHTS,
             PROCESS_NAME_FORMAT,
             OpenProcess,
             QueryFullProcessImageNameW,
         },
-        Windows::Win32::UI::KeyboardAndMouseInput::SetFocus,
-        Windows::Win32::UI::Accessibility::{SetWinEventHook, HWINEVENTHOOK},
+        Windows::Win32::UI::KeyboardAndMouseInput::{
+            GetAsyncKeyState,
+            SendInput,
+            IsWindowEnabled,
+            SetFocus,
+            INPUT,
+            INPUT_0,
+            MOUSEINPUT,
+        },
+        Windows::Win32::UI::Accessibility::{
+            SetWinEventHook,
+            NotifyWinEvent,
+            HWINEVENTHOOK,
+            CAccPropServices,
+            IAccPropServices,
+            LiveSetting_Property_GUID,
+            CUIAutomation,
+            IUIAutomation,
+            IUIAutomationCondition,
+            IUIAutomationElement,
+            IUIAutomationElementArray,
+            TreeScope_Children,
+        },
         Windows::Win32::UI::WindowsAndMessaging::*,
     );
 }
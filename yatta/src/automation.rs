@@ -0,0 +1,132 @@
+use std::{collections::HashMap, mem};
+
+use log::info;
+
+use bindings::Windows::Win32::{Foundation::SYSTEMTIME, System::SystemInformation::GetLocalTime};
+use yatta_core::SocketMessage;
+
+use crate::{
+    config::{Automation, Condition},
+    desktop::open_windows,
+    window::Window,
+    windows_event::WindowsEventType,
+};
+
+/// The automations from the config, along with which of them currently apply
+pub struct Automations {
+    automations: Vec<Automation>,
+    active:      Vec<bool>,
+    /// The exe of every open window by hwnd, only kept if an automation
+    /// depends on which exes have windows. Looking up the exe of every open
+    /// window is slow, so this is only done once and then kept up to date
+    /// from the windows events.
+    exes:        Option<HashMap<isize, String>>,
+}
+
+impl Automations {
+    pub fn new(automations: Vec<Automation>) -> Self {
+        let active = vec![false; automations.len()];
+        let needs_exes = automations
+            .iter()
+            .any(|automation| matches!(automation.when, Condition::ExeHasWindow(_)));
+        let exes = if needs_exes {
+            Option::from(
+                open_windows()
+                    .iter()
+                    .map(|window| (window.hwnd.0, window.exe_name()))
+                    .collect::<HashMap<_, _>>(),
+            )
+        } else {
+            None
+        };
+
+        Automations {
+            automations,
+            active,
+            exes,
+        }
+    }
+
+    /// Keeps track of the exes with windows as windows are shown, hidden and
+    /// destroyed
+    pub fn track(&mut self, event_type: WindowsEventType, window: &Window) {
+        if let Some(exes) = &mut self.exes {
+            match event_type {
+                WindowsEventType::Show => {
+                    exes.insert(window.hwnd.0, window.exe_name());
+                }
+                WindowsEventType::Hide | WindowsEventType::Destroy => {
+                    exes.remove(&window.hwnd.0);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Checks every condition again, returning the commands of the
+    /// automations that have started or stopped applying since the last check
+    pub fn evaluate(&mut self) -> Vec<SocketMessage> {
+        if self.automations.is_empty() {
+            return vec![];
+        }
+
+        let now = minutes_since_midnight();
+
+        let exes = &self.exes;
+        let has_window = |exe: &str| matches!(exes, Some(exes) if exes.values().any(|x| x == exe));
+
+        let mut messages = vec![];
+        for (automation, active) in self.automations.iter().zip(self.active.iter_mut()) {
+            let applies = match &automation.when {
+                Condition::ExeHasWindow(exe) => has_window(exe),
+                // Times are checked when the config is loaded
+                Condition::After(time) => matches!(parse_time(time), Some(time) if now >= time),
+            };
+
+            if applies == *active {
+                continue;
+            }
+
+            *active = applies;
+            info!(
+                "automation {:?} {}",
+                automation.when,
+                if applies {
+                    "applies"
+                } else {
+                    "no longer applies"
+                }
+            );
+
+            if applies {
+                messages.extend(automation.then.iter().cloned());
+            } else {
+                messages.extend(automation.otherwise.iter().cloned());
+            }
+        }
+
+        messages
+    }
+}
+
+/// Minutes since midnight of a time written like 18:00
+pub fn parse_time(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.trim().parse().ok()?, minutes.trim().parse().ok()?);
+
+    if hours < 24 && minutes < 60 {
+        Option::from(hours * 60 + minutes)
+    } else {
+        None
+    }
+}
+
+fn minutes_since_midnight() -> u32 {
+    let time = unsafe {
+        let mut time: SYSTEMTIME = mem::zeroed();
+        GetLocalTime(&mut time);
+        time
+    };
+
+    u32::from(time.wHour) * 60 + u32::from(time.wMinute)
+}
@@ -0,0 +1,615 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sysinfo::{System, SystemExt};
+use yatta_core::{Layout, SocketMessage, WindowRule};
+
+use crate::{
+    automation::parse_time,
+    quirks::Quirks,
+    window::FloatWindowRule,
+    windows_event::WinEventCode,
+};
+
+/// How the display that commands act on is chosen when none is given
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActiveDisplay {
+    /// The display under the cursor
+    Cursor,
+    /// The display of the foreground window, falling back to the display under
+    /// the cursor when there is no foreground window or it is the desktop
+    ForegroundWindow,
+}
+
+/// What focusing a window in a direction does when there is no window in that
+/// direction
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FocusWrap {
+    /// Leave focus where it is
+    Off,
+    /// Wrap around to the window on the opposite edge of the same display
+    SameDisplay,
+    /// Continue on to the nearest window on the display in that direction
+    AdjacentDisplay,
+}
+
+/// How windows are ordered when yatta moves them
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ZOrder {
+    /// Tiled windows are sent to the bottom and floating windows brought to
+    /// the top, so floating windows never end up behind a tile
+    Layered,
+    /// Windows stay where they are in the z-order, only zoomed, Monocle and
+    /// dragged windows are brought to the top
+    Preserve,
+}
+
+/// Whether yatta works around the borders and rounded corners that Windows 11
+/// draws around windows
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Windows11Compat {
+    /// On when yatta is running on Windows 11
+    Auto,
+    On,
+    Off,
+}
+
+lazy_static! {
+    // Windows 11 still calls itself Windows 10, only the build numbers set it
+    // apart
+    static ref WINDOWS_11: bool = matches!(
+        System::new().get_kernel_version().map(|build| build.parse::<u32>()),
+        Some(Ok(build)) if build >= 22000
+    );
+}
+
+impl Windows11Compat {
+    pub fn enabled(self) -> bool {
+        match self {
+            Windows11Compat::Auto => *WINDOWS_11,
+            Windows11Compat::On => true,
+            Windows11Compat::Off => false,
+        }
+    }
+}
+
+/// Which window events yatta reacts to, for working around apps that send
+/// events at odd times
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventFilter {
+    /// Only react to these events, or to every event yatta handles when empty
+    pub allow: Vec<WinEventCode>,
+    /// Never react to these events
+    pub deny:  Vec<WinEventCode>,
+}
+
+impl EventFilter {
+    pub fn allows(&self, event_code: WinEventCode) -> bool {
+        (self.allow.is_empty() || self.allow.contains(&event_code))
+            && !self.deny.contains(&event_code)
+    }
+}
+
+/// A key that changes what dragging a window does while it is held
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Modifier {
+    Alt,
+    Control,
+    Shift,
+    Win,
+}
+
+impl Modifier {
+    pub fn virtual_key(self) -> i32 {
+        match self {
+            Modifier::Alt => 0x12,
+            Modifier::Control => 0x11,
+            Modifier::Shift => 0x10,
+            Modifier::Win => 0x5B,
+        }
+    }
+}
+
+/// What happens when a tiled window is dropped after being dragged
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DragAction {
+    /// Swap it with the window under the cursor
+    Swap,
+    /// Change the layout to fit the window's new size, which only works when
+    /// it was dragged by a border
+    Resize,
+    /// Float it where it was dropped
+    Float,
+    /// Put it back in its tile
+    Snap,
+}
+
+/// What dragging a tiled window does, depending on how it is dragged
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DragConfig {
+    pub modifier:                Modifier,
+    pub title_bar:               DragAction,
+    /// Dragging by the title bar while holding down the modifier. When this is
+    /// float, windows picked up with the modifier held are floated straight
+    /// away, and can then be put down anywhere.
+    pub title_bar_with_modifier: DragAction,
+    pub border:                  DragAction,
+}
+
+impl Default for DragConfig {
+    fn default() -> Self {
+        DragConfig {
+            modifier:                Modifier::Alt,
+            title_bar:               DragAction::Swap,
+            title_bar_with_modifier: DragAction::Float,
+            border:                  DragAction::Resize,
+        }
+    }
+}
+
+/// What happens to the other windows on a display in the Monocle layout
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MonocleOthers {
+    /// Leave them where they are behind the monocle window
+    Visible,
+    /// Minimize them, they can still be brought back from the taskbar
+    Minimize,
+    /// Hide them completely; if yatta is killed while in Monocle they stay
+    /// hidden
+    Hide,
+}
+
+/// What happens to dialog boxes, like file pickers and settings windows
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DialogBehaviour {
+    /// Float them in the middle of the display they open on
+    FloatCentered,
+    /// Tile them like any other window
+    Tile,
+    /// Leave them alone entirely
+    Ignore,
+}
+
+/// A length relative to the display, or in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Length {
+    Percent(i32),
+    Pixels(i32),
+}
+
+impl Length {
+    /// The length in pixels out of `total`, never more than `total`
+    pub fn of(self, total: i32) -> i32 {
+        match self {
+            Length::Percent(percent) => total * percent.clamp(0, 100) / 100,
+            Length::Pixels(pixels) => pixels.clamp(0, total),
+        }
+    }
+}
+
+/// How big a window is when it is floated in the middle of its display
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FloatSize {
+    pub width:  Length,
+    pub height: Length,
+}
+
+impl Default for FloatSize {
+    fn default() -> Self {
+        FloatSize {
+            width:  Length::Percent(50),
+            height: Length::Percent(50),
+        }
+    }
+}
+
+/// When the mouse cursor follows focus to the window being focused
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CursorWarp {
+    /// Whenever yatta changes the focus
+    Always,
+    /// Only when the focus moves to another display
+    DisplayChange,
+    /// Never, the cursor stays wherever it is
+    Never,
+}
+
+/// Where new windows go in the window list
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NewWindowPosition {
+    /// In the spot after the focused window
+    AfterFocused,
+    /// In the tile under the mouse cursor, pushing the window there along
+    UnderCursor,
+}
+
+/// Which window is focused when the focused window is closed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FocusAfterClose {
+    /// The window before it in the window list
+    Previous,
+    /// The window after it in the window list
+    Next,
+    /// The window on the display that was focused most recently before it
+    Recent,
+    /// The master window, or the first window if there isn't one
+    Master,
+}
+
+/// Which tiled window takes over the position of one that is closed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpaceInheritance {
+    /// The window after it, with the rest of the windows moving up
+    Next,
+    /// The window before it
+    Previous,
+    /// Whichever window is biggest on screen
+    Largest,
+}
+
+/// What happens to the windows of a process after the first one to be tiled,
+/// for apps like Visual Studio that open several top level windows
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MultiWindowBehaviour {
+    /// Tile them like any other window
+    Tile,
+    /// Leave them floating where they opened
+    Float,
+    /// Leave them alone entirely
+    Ignore,
+}
+
+/// Settings for a single display, listed in display number order. Anything
+/// left out falls back to the top level setting.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplayConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout:        Option<Layout>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inner_gap:     Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outer_gap:     Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub columns:       Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rows:          Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub centred_width: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monocle_width: Option<i32>,
+}
+
+/// Layout requirements of an exe that misbehaves under some layouts, applied
+/// to any display it has a window on
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayoutRule {
+    pub exe:    String,
+    /// Switch to this layout while the exe has a window on the display
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout: Option<Layout>,
+    /// Never use these layouts while the exe has a window on the display
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub avoid:  Vec<Layout>,
+}
+
+/// Settings used in place of the top level ones while the connected displays
+/// match, for switching between setups like docked and laptop only. The
+/// first matching profile is used.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub name:          String,
+    /// Matches when exactly this many displays are connected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_count: Option<usize>,
+    /// Matches when the displays have these resolutions in display number
+    /// order, written like 2560x1440
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub resolutions:   Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout:        Option<Layout>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inner_gap:     Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outer_gap:     Option<i32>,
+    /// Replaces the top level displays list
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub displays:      Option<Vec<DisplayConfig>>,
+}
+
+impl Profile {
+    pub fn matches(&self, resolutions: &[String]) -> bool {
+        let count_matches = match self.display_count {
+            Some(count) => count == resolutions.len(),
+            None => true,
+        };
+
+        count_matches && (self.resolutions.is_empty() || self.resolutions == resolutions)
+    }
+}
+
+/// Something about the desktop that automations can react to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    /// A window of this exe is open, whether yatta manages it or not
+    ExeHasWindow(String),
+    /// The local time is past this time of day, written like 18:00. This
+    /// stops being true at midnight.
+    After(String),
+}
+
+/// yattac commands sent when a condition starts or stops being true, like
+/// pausing tiling while a meeting app is open
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Automation {
+    pub when:      Condition,
+    #[serde(default)]
+    pub then:      Vec<SocketMessage>,
+    #[serde(default)]
+    pub otherwise: Vec<SocketMessage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub float_classes:                  Vec<String>,
+    pub float_exes:                     Vec<String>,
+    pub float_titles:                   Vec<String>,
+    /// Windows matching any of these are floated, see `WindowRule`
+    pub float_windows:                  Vec<WindowRule>,
+    /// Exes whose splash screens should never be tiled
+    pub splash_exes:                    Vec<String>,
+    /// Exes that only send a name change rather than a Show event when they
+    /// launch, every time one of their windows changes name it is handled as
+    /// being shown
+    pub name_change_on_launch_exes:     Vec<String>,
+    /// Enumerate windows on startup but don't move anything until tiling is
+    /// resumed
+    pub start_paused:                   bool,
+    /// Tile the windows that are already open on startup, otherwise only
+    /// windows created after startup are managed
+    pub initial_tile:                   bool,
+    /// Click in the middle of an empty display when focusing it instead of
+    /// focusing the desktop window
+    pub focus_empty_display_with_click: bool,
+    /// Move the cursor to the display being focused, otherwise only the
+    /// foreground window changes and the cursor is left where it is
+    pub warp_cursor_on_display_focus:   bool,
+    pub cursor_warp:                    CursorWarp,
+    pub active_display:                 ActiveDisplay,
+    pub focus_wrap:                     FocusWrap,
+    /// Drop the gaps when a display has a single tiled window or is in the
+    /// Monocle layout
+    pub smart_gaps:                     bool,
+    pub monocle_others:                 MonocleOthers,
+    /// Keep the outer gap around the window in the Monocle layout
+    pub monocle_outer_gap:              bool,
+    pub layout:                         Layout,
+    pub inner_gap:                      i32,
+    pub outer_gap:                      i32,
+    /// Pixels a window grows or shrinks by with yattac resize
+    pub resize_step:                    i32,
+    /// Number of columns in the Columns layout, 0 for one column per window
+    pub columns:                        usize,
+    /// Number of rows in the Rows layout, 0 for one row per window
+    pub rows:                           usize,
+    /// Width of the centred window in the Centred layout, as a percentage of
+    /// the display
+    pub centred_width:                  i32,
+    /// Width of the window in the Monocle layout, as a percentage of the
+    /// display. Anything under 100 centres it with blank space either side.
+    pub monocle_width:                  i32,
+    /// How much bigger than its tile a window is drawn when it is zoomed, as a
+    /// percentage
+    pub zoom_percent:                   i32,
+    pub z_order:                        ZOrder,
+    /// Bring windows to the front of the other windows yatta manages when they
+    /// are focused, for when zoomed or floating windows overlap
+    pub raise_on_focus:                 bool,
+    /// Wait until no mouse button is held before moving windows, for when
+    /// clicks on title bar buttons go missing as windows are moved
+    pub defer_layout_while_clicking:    bool,
+    pub displays:                       Vec<DisplayConfig>,
+    /// Checked whenever windows are added to or removed from a display. The
+    /// first matching rule with a layout wins, and the layouts avoided by
+    /// every matching rule are skipped.
+    pub layout_rules:                   Vec<LayoutRule>,
+    /// Windows of these exes are kept in the first position of the display
+    /// they open on, see `yattac toggle-master`
+    pub master_exes:                    Vec<String>,
+    /// Checked whenever displays are connected, disconnected or change
+    /// resolution
+    pub profiles:                       Vec<Profile>,
+    /// Checked every minute, and whenever a window is shown, hidden or
+    /// destroyed
+    pub automations:                    Vec<Automation>,
+    /// Remember the display that windows of each exe were last moved to and
+    /// tile new windows of that exe there, saved in ~/yatta-placements.json
+    pub placement_history:              bool,
+    /// Log a warning whenever handling a single event or command takes longer
+    /// than this many milliseconds
+    pub slow_handler_threshold_ms:      u64,
+    pub dialog_behaviour:               DialogBehaviour,
+    pub new_window_position:            NewWindowPosition,
+    pub space_inheritance:              SpaceInheritance,
+    pub focus_after_close:              FocusAfterClose,
+    /// Used when a window is floated with ToggleFloat or is left floating
+    /// when Monocle is turned off
+    pub float_size:                     FloatSize,
+    /// Overrides float_size for specific exes
+    pub float_size_exes:                HashMap<String, FloatSize>,
+    pub drag:                           DragConfig,
+    pub events:                         EventFilter,
+    /// With no inner gap, overlap neighbouring tiles by the border Windows 11
+    /// draws around windows so that they share a single line
+    pub windows_11_compat:              Windows11Compat,
+    /// Ask Windows 11 not to round the corners of tiled windows while
+    /// windows_11_compat is on, so that neighbouring tiles line up
+    pub square_corners:                 bool,
+    /// Line up the part of each tiled window that can be seen with its tile,
+    /// rather than the drop shadow and invisible resize borders around it,
+    /// so that the gaps look even
+    pub compensate_frames:              bool,
+    /// Only tile a new window if it is still open and visible this many
+    /// milliseconds after it is shown, so that toasts and other windows that
+    /// are gone again straight away don't make the layout jump. 0 tiles
+    /// windows straight away.
+    pub show_delay_ms:                  u64,
+    /// Windows of these exes are tiled straight away whatever show_delay_ms
+    /// is
+    pub immediate_show_exes:            Vec<String>,
+    /// Oddities of specific exes to work around, replacing the quirks yatta
+    /// knows about already for those exes
+    pub quirks:                         HashMap<String, Quirks>,
+    /// Spell out the active display, its layout, how many windows it has and
+    /// the focused window in the title of the yatta_status window, and have
+    /// screen readers announce it whenever it changes
+    pub screen_reader:                  bool,
+    /// Also look for windows opening and closing by asking UI Automation for
+    /// the top level windows this often, in milliseconds, for apps that don't
+    /// send the WinEvents yatta listens for. Walking the UIA tree is not
+    /// cheap, so 0 turns it off.
+    pub uia_discovery_poll_ms:          u64,
+    /// Check the work area of every display this often and re-tile if it has
+    /// changed, for bars that reserve space without telling anyone. 0 turns
+    /// polling off.
+    pub work_area_poll_secs:            u64,
+    /// Defaults to tiling every window, since browsers and the like run all of
+    /// their windows in one process
+    pub multi_window:                   MultiWindowBehaviour,
+    /// Overrides multi_window for specific exes
+    pub multi_window_exes:              HashMap<String, MultiWindowBehaviour>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            float_classes:                  vec![],
+            float_exes:                     vec![],
+            float_titles:                   vec![],
+            float_windows:                  vec![],
+            splash_exes:                    vec![],
+            name_change_on_launch_exes:     vec![],
+            start_paused:                   false,
+            initial_tile:                   true,
+            focus_empty_display_with_click: false,
+            warp_cursor_on_display_focus:   true,
+            cursor_warp:                    CursorWarp::Always,
+            active_display:                 ActiveDisplay::Cursor,
+            focus_wrap:                     FocusWrap::Off,
+            smart_gaps:                     false,
+            monocle_others:                 MonocleOthers::Visible,
+            monocle_outer_gap:              true,
+            layout:                         Layout::BSPV,
+            inner_gap:                      10,
+            outer_gap:                      25,
+            resize_step:                    50,
+            columns:                        0,
+            rows:                           0,
+            centred_width:                  60,
+            monocle_width:                  100,
+            zoom_percent:                   150,
+            z_order:                        ZOrder::Layered,
+            raise_on_focus:                 false,
+            defer_layout_while_clicking:    false,
+            displays:                       vec![],
+            layout_rules:                   vec![],
+            master_exes:                    vec![],
+            profiles:                       vec![],
+            automations:                    vec![],
+            placement_history:              false,
+            slow_handler_threshold_ms:      100,
+            dialog_behaviour:               DialogBehaviour::Ignore,
+            new_window_position:            NewWindowPosition::AfterFocused,
+            space_inheritance:              SpaceInheritance::Next,
+            focus_after_close:              FocusAfterClose::Previous,
+            float_size:                     FloatSize::default(),
+            float_size_exes:                HashMap::new(),
+            drag:                           DragConfig::default(),
+            events:                         EventFilter::default(),
+            windows_11_compat:              Windows11Compat::Auto,
+            square_corners:                 false,
+            compensate_frames:              false,
+            show_delay_ms:                  0,
+            immediate_show_exes:            vec![],
+            quirks:                         HashMap::new(),
+            screen_reader:                  false,
+            uia_discovery_poll_ms:          0,
+            work_area_poll_secs:            0,
+            multi_window:                   MultiWindowBehaviour::Tile,
+            multi_window_exes:              HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("could not read config file {}", path.display()))?;
+
+        let config: Config = serde_yaml::from_str(&contents)
+            .with_context(|| format!("could not parse config file {}", path.display()))?;
+
+        for rule in &config.float_windows {
+            FloatWindowRule::new(rule.clone())
+                .with_context(|| format!("invalid rule in config file {}", path.display()))?;
+        }
+
+        for automation in &config.automations {
+            if let Condition::After(time) = &automation.when {
+                if parse_time(time).is_none() {
+                    bail!(
+                        "invalid automation in config file {}: {} is not a time of day like 18:00",
+                        path.display(),
+                        time
+                    );
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// The first profile that matches displays with these resolutions
+    pub fn profile(&self, resolutions: &[String]) -> Option<&Profile> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.matches(resolutions))
+    }
+
+    /// This config with the settings that `profile` sets in place of the top
+    /// level ones
+    pub fn with_profile(&self, profile: &Profile) -> Config {
+        let mut config = self.clone();
+
+        config.layout = profile.layout.unwrap_or(config.layout);
+        config.inner_gap = profile.inner_gap.unwrap_or(config.inner_gap);
+        config.outer_gap = profile.outer_gap.unwrap_or(config.outer_gap);
+        if let Some(displays) = &profile.displays {
+            config.displays = displays.clone();
+        }
+
+        config
+    }
+}
@@ -1,6 +1,10 @@
-use std::{borrow::BorrowMut, cmp::Ordering, mem};
-
-use enigo::{Enigo, MouseButton, MouseControllable};
+use std::{
+    borrow::BorrowMut,
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+    mem,
+    time::{Duration, Instant},
+};
 
 use bindings::Windows::Win32::{
     Foundation::{BOOL, HWND, LPARAM, POINT, RECT},
@@ -13,69 +17,856 @@ use bindings::Windows::Win32::{
         HMONITOR,
         MONITORINFO,
         MONITOR_DEFAULTTONEAREST,
+        MONITOR_DEFAULTTONULL,
         MONITOR_DEFAULTTOPRIMARY,
     },
-    UI::WindowsAndMessaging::{
-        EnumWindows,
-        GetCursorPos,
-        SetCursorPos,
-        HWND_NOTOPMOST,
-        SWP_NOMOVE,
-        SWP_NOSIZE,
+    UI::{
+        KeyboardAndMouseInput::{
+            GetAsyncKeyState,
+            SendInput,
+            INPUT,
+            INPUT_0,
+            INPUT_MOUSE,
+            MOUSEEVENTF_LEFTDOWN,
+            MOUSEEVENTF_LEFTUP,
+            MOUSEINPUT,
+        },
+        WindowsAndMessaging::{
+            EnumWindows,
+            GetCursorPos,
+            SetCursorPos,
+            HWND_BOTTOM,
+            HWND_TOP,
+            SWP_NOMOVE,
+            SWP_NOSIZE,
+        },
+    },
+};
+use log::{error, info};
+use yatta_core::{
+    state::{DesiredDisplay, DesiredState},
+    CycleDirection,
+    Layout,
+    OperationDirection,
+    ResizeEdge,
+    Rules,
+    Sizing,
+};
+
+use crate::{
+    config::{
+        ActiveDisplay,
+        Config,
+        CursorWarp,
+        DialogBehaviour,
+        DisplayConfig,
+        DragAction,
+        DragConfig,
+        FloatSize,
+        FocusAfterClose,
+        FocusWrap,
+        LayoutRule,
+        Modifier,
+        MonocleOthers,
+        MultiWindowBehaviour,
+        NewWindowPosition,
+        SpaceInheritance,
+        ZOrder,
     },
+    metrics::StartupProfile,
+    placement::{PlacementHistory, PLACEMENT_HISTORY_FILE},
+    quirks,
+    rect::Rect,
+    window::{compile_float_windows, FloatWindowRule, Layer, Window},
+    windows_event::{WindowsEvent, WindowsEventType},
+    DirectionOperation,
 };
-use yatta_core::{CycleDirection, Layout, ResizeEdge, Sizing};
 
-use crate::{rect::Rect, window::Window, DirectionOperation, PADDING};
+/// How many of the windows that have been in a position are remembered
+const OCCUPANT_HISTORY: usize = 8;
+
+/// Virtual key codes of the left, right and middle mouse buttons
+const VK_LBUTTON: i32 = 0x01;
+const VK_RBUTTON: i32 = 0x02;
+const VK_MBUTTON: i32 = 0x04;
+
+/// Width of the border Windows 11 draws around every window
+const WINDOWS_11_BORDER: i32 = 1;
 
 #[derive(Debug, Clone)]
 pub struct Desktop {
-    pub displays: Vec<Display>,
-    pub paused:   bool,
+    pub displays:                       Vec<Display>,
+    pub paused:                         bool,
+    pub focus_empty_display_with_click: bool,
+    pub warp_cursor_on_display_focus:   bool,
+    pub active_display:                 ActiveDisplay,
+    pub focus_wrap:                     FocusWrap,
+    /// Only kept when placement_history is enabled in the config
+    pub placement_history:              Option<PlacementHistory>,
+    pub dialog_behaviour:               DialogBehaviour,
+    pub drag:                           DragConfig,
+    pub multi_window:                   MultiWindowBehaviour,
+    pub multi_window_exes:              HashMap<String, MultiWindowBehaviour>,
+    /// Starts out with the rules from the config, yattac can add more
+    pub rules:                          Rules,
+    /// `rules.float_windows`, compiled
+    pub float_windows:                  Vec<FloatWindowRule>,
+    /// Name of the profile matching the connected displays, if any
+    pub profile:                        Option<String>,
+    /// When the open transaction was begun
+    pub transaction:                    Option<Instant>,
+    /// How long show events are held back for, see `delay_show`
+    pub show_delay:                     Duration,
+    pub immediate_show_exes:            Vec<String>,
+    /// Show events that have been held back, with when they are due
+    pub delayed_shows:                  Vec<(Instant, WindowsEvent)>,
+    /// The windows that were managed when yatta was suspended, until it is
+    /// resumed
+    pub suspended:                      Option<Vec<Window>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Display {
-    pub windows:           Vec<Window>,
-    pub hmonitor:          HMONITOR,
-    dimensions:            Rect,
-    pub layout:            Layout,
-    pub layout_dimensions: Vec<Rect>,
-    pub foreground_window: Window,
-    pub gaps:              i32,
-    pub padding:           i32,
-    pub resize_step:       i32,
+    pub windows:              Vec<Window>,
+    pub hmonitor:             HMONITOR,
+    dimensions:               Rect,
+    pub layout:               Layout,
+    /// Layout to go back to when Monocle is toggled off
+    pub previous_layout:      Layout,
+    /// The layout that was picked for the display, while a layout rule is
+    /// using a different one
+    pub chosen_layout:        Option<Layout>,
+    pub layout_rules:         Vec<LayoutRule>,
+    pub layout_dimensions:    Vec<Rect>,
+    pub foreground_window:    Window,
+    /// Space between neighbouring tiles
+    pub inner_gap:            i32,
+    /// Space between the tiles and the edges of the display
+    pub outer_gap:            i32,
+    /// Ignore both gaps when there is only one tile on the display
+    pub smart_gaps:           bool,
+    pub monocle_others:       MonocleOthers,
+    pub monocle_outer_gap:    bool,
+    pub resize_step:          i32,
+    /// Number of columns in the Columns layout, 0 for one column per window
+    pub columns:              usize,
+    /// Number of rows in the Rows layout, 0 for one row per window
+    pub rows:                 usize,
+    /// Percentage of the display width taken up by the centred window in the
+    /// Centred layout
+    pub centred_width:        i32,
+    /// Percentage of the display width taken up by the window in the Monocle
+    /// layout
+    pub monocle_width:        i32,
+    /// How much bigger a zoomed window is than its tile, as a percentage
+    pub zoom_percent:         i32,
+    /// The window drawn bigger than its tile and on top of its neighbours
+    pub zoomed:               Option<HWND>,
+    pub z_order:              ZOrder,
+    pub raise_on_focus:       bool,
+    pub cursor_warp:          CursorWarp,
+    /// Hold off moving windows while a mouse button is down
+    pub defer_while_clicking: bool,
+    /// Make room in the gap math for the border Windows 11 draws around
+    /// windows
+    pub windows_11_compat:    bool,
+    /// Tiled windows are drawn without rounded corners
+    pub square_corners:       bool,
+    /// Positions are for the part of a window that can be seen, without its
+    /// drop shadow and invisible borders
+    pub compensate_frames:    bool,
+    /// The gaps have changed, a click was in progress or a transaction is
+    /// open, and the windows haven't been moved to match yet
+    pub layout_pending:       bool,
+    /// Layouts are held back until the transaction is ended
+    pub in_transaction:       bool,
+    /// Kept in the first position of the window list, with new and promoted
+    /// windows going after it
+    pub master:               Option<HWND>,
+    /// Windows of these exes become the master of the display they open on if
+    /// it doesn't have one
+    pub master_exes:          Vec<String>,
+    pub new_window_position:  NewWindowPosition,
+    pub space_inheritance:    SpaceInheritance,
+    pub focus_after_close:    FocusAfterClose,
+    /// Windows in the order they were focused, most recent first
+    pub focus_history:        Vec<HWND>,
+    pub float_size:           FloatSize,
+    pub float_size_exes:      HashMap<String, FloatSize>,
+    /// The windows that were tiled before every window was floated with
+    /// `float_all`
+    pub floated_all:          Option<Vec<HWND>>,
+    /// The windows that have been in each position of the window list, most
+    /// recent first
+    pub occupants:            Vec<VecDeque<HWND>>,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Display {
+            windows:              vec![],
+            hmonitor:             HMONITOR(0),
+            dimensions:           Rect::zero(),
+            layout:               Layout::BSPV,
+            previous_layout:      Layout::BSPV,
+            chosen_layout:        None,
+            layout_rules:         vec![],
+            layout_dimensions:    vec![],
+            foreground_window:    Window::default(),
+            inner_gap:            10,
+            outer_gap:            25,
+            smart_gaps:           false,
+            monocle_others:       MonocleOthers::Visible,
+            monocle_outer_gap:    true,
+            resize_step:          50,
+            columns:              0,
+            rows:                 0,
+            centred_width:        60,
+            monocle_width:        100,
+            zoom_percent:         150,
+            zoomed:               None,
+            z_order:              ZOrder::Layered,
+            raise_on_focus:       false,
+            cursor_warp:          CursorWarp::Always,
+            defer_while_clicking: false,
+            windows_11_compat:    false,
+            square_corners:       false,
+            compensate_frames:    false,
+            layout_pending:       false,
+            in_transaction:       false,
+            master:               None,
+            master_exes:          vec![],
+            new_window_position:  NewWindowPosition::AfterFocused,
+            space_inheritance:    SpaceInheritance::Next,
+            focus_after_close:    FocusAfterClose::Previous,
+            focus_history:        vec![],
+            float_size:           FloatSize::default(),
+            float_size_exes:      HashMap::new(),
+            floated_all:          None,
+            occupants:            vec![],
+        }
+    }
 }
 
 impl Display {
+    /// Resolution of the whole display, not just its work area, like 2560x1440
+    pub fn resolution(&self) -> String {
+        let rect: Rect = unsafe {
+            let mut info: MONITORINFO = mem::zeroed();
+            info.cbSize = mem::size_of::<MONITORINFO>() as u32;
+
+            GetMonitorInfoW(self.hmonitor, &mut info as *mut MONITORINFO as *mut _);
+
+            info.rcMonitor.into()
+        };
+
+        format!("{}x{}", rect.width, rect.height)
+    }
+
+    /// The work area as Windows currently reports it, which can drift from
+    /// `dimensions` without any event when bars reserve space
+    pub fn work_area(&self) -> Rect {
+        unsafe {
+            let mut info: MONITORINFO = mem::zeroed();
+            info.cbSize = mem::size_of::<MONITORINFO>() as u32;
+
+            GetMonitorInfoW(self.hmonitor, &mut info as *mut MONITORINFO as *mut _);
+
+            info.rcWork.into()
+        }
+    }
+
     pub fn get_dimensions(&self) -> Rect {
         let mut rect = self.dimensions;
 
-        let padding = PADDING.lock().unwrap();
-
-        rect.height -= *padding * 2;
-        rect.width -= *padding * 2;
-        rect.y += *padding;
-        rect.x += *padding;
+        rect.height -= self.outer_gap * 2;
+        rect.width -= self.outer_gap * 2;
+        rect.y += self.outer_gap;
+        rect.x += self.outer_gap;
 
         rect
     }
 
-    pub fn get_foreground_window(&mut self) {
-        self.foreground_window = Window::foreground();
+    /// Applies the config to the display with index `idx`, preferring any
+    /// settings given for it specifically
+    pub fn configure(&mut self, config: &Config, idx: usize) {
+        let overrides = config.displays.get(idx).cloned().unwrap_or_default();
+
+        self.layout = overrides.layout.unwrap_or(config.layout);
+        self.chosen_layout = None;
+        self.layout_rules = config.layout_rules.clone();
+        self.master_exes = config.master_exes.clone();
+        self.new_window_position = config.new_window_position;
+        self.space_inheritance = config.space_inheritance;
+        self.focus_after_close = config.focus_after_close;
+        self.float_size = config.float_size;
+        self.float_size_exes = config.float_size_exes.clone();
+        self.inner_gap = overrides.inner_gap.unwrap_or(config.inner_gap);
+        self.outer_gap = overrides.outer_gap.unwrap_or(config.outer_gap);
+        self.columns = overrides.columns.unwrap_or(config.columns);
+        self.rows = overrides.rows.unwrap_or(config.rows);
+        self.centred_width = overrides.centred_width.unwrap_or(config.centred_width);
+        self.monocle_width = overrides.monocle_width.unwrap_or(config.monocle_width);
+        self.resize_step = config.resize_step;
+        self.zoom_percent = config.zoom_percent;
+        self.z_order = config.z_order;
+        self.raise_on_focus = config.raise_on_focus;
+        self.cursor_warp = config.cursor_warp;
+        self.defer_while_clicking = config.defer_layout_while_clicking;
+        self.windows_11_compat = config.windows_11_compat.enabled();
+        self.square_corners = self.windows_11_compat && config.square_corners;
+        self.compensate_frames = config.compensate_frames;
+        self.smart_gaps = config.smart_gaps;
+        self.monocle_others = config.monocle_others;
+        self.monocle_outer_gap = config.monocle_outer_gap;
+    }
+
+    /// Settings of this display that differ from the top level settings in
+    /// `config`
+    pub fn overrides(&self, config: &Config) -> DisplayConfig {
+        DisplayConfig {
+            layout:        Some(self.chosen_layout.unwrap_or(self.layout))
+                .filter(|x| *x != config.layout),
+            inner_gap:     Some(self.inner_gap).filter(|x| *x != config.inner_gap),
+            outer_gap:     Some(self.outer_gap).filter(|x| *x != config.outer_gap),
+            columns:       Some(self.columns).filter(|x| *x != config.columns),
+            rows:          Some(self.rows).filter(|x| *x != config.rows),
+            centred_width: Some(self.centred_width).filter(|x| *x != config.centred_width),
+            monocle_width: Some(self.monocle_width).filter(|x| *x != config.monocle_width),
+        }
+    }
+
+    /// Starts managing `window` in the spot after the foreground window, or
+    /// in the tile under the cursor if configured to, returning false if it is
+    /// already being managed. Some apps like Windows Terminal send multiple
+    /// Show events on startup.
+    pub fn manage_window(&mut self, mut window: Window) -> bool {
+        if self.windows.contains(&window) {
+            return false;
+        }
+
+        let takes_master = self.master.is_none()
+            && !self.master_exes.is_empty()
+            && self.master_exes.contains(&window.exe_name());
+
+        let idx = if takes_master {
+            self.master = Option::from(window.hwnd);
+            0
+        } else if self.windows.is_empty() {
+            0
+        } else {
+            let after_focused = self.get_foreground_window_index() + 1;
+            let idx = match self.new_window_position {
+                NewWindowPosition::AfterFocused => after_focused,
+                NewWindowPosition::UnderCursor => self.tile_under_cursor().unwrap_or(after_focused),
+            };
+
+            idx.max(self.first_free_idx())
+        };
+
+        // If we are inserting where there is a window that has resize adjustments, take
+        // over those resize adjustments and remove them from the window that is
+        // currently there
+        if let Some(current_window) = self.windows.get_mut(idx) {
+            let resize = current_window.resize;
+            current_window.resize = None;
+            window.resize = resize;
+        }
+
+        self.windows.insert(idx, window);
+        true
+    }
+
+    /// Records that `window` has been focused, returning false if it isn't
+    /// managed on this display. Focus doesn't change where windows go, except
+    /// in Monocle where the focused window is the one that is shown.
+    pub fn focus_changed(&mut self, window: Window) -> bool {
+        if !self.windows.contains(&window) {
+            return false;
+        }
+
+        self.foreground_window = window;
+
+        let windows = &self.windows;
+        self.focus_history
+            .retain(|hwnd| *hwnd != window.hwnd && windows.iter().any(|w| w.hwnd == *hwnd));
+        self.focus_history.insert(0, window.hwnd);
+
+        if matches!(self.layout, Layout::Monocle) {
+            self.apply_layout(None);
+        } else if self.raise_on_focus {
+            window.raise(self.insert_after(Layer::Raised));
+        }
+
+        true
+    }
+
+    /// Stops managing `window`, returning the index of the window picked by
+    /// `focus_after_close` to be focused in its place
+    pub fn unmanage_window(&mut self, window: &Window) -> usize {
+        let idx = match window.index(&self.windows) {
+            Some(idx) => idx,
+            None => return 0,
+        };
+
+        let tiled = self.windows[idx].should_tile();
+        let slot = self.windows[..idx]
+            .iter()
+            .filter(|w| w.should_tile())
+            .count();
+
+        // If we are removing a window that has resize adjustments, take over those
+        // resize adjustments and add them from the window that is going to take the
+        // space of the window being removed
+        if idx > 0 {
+            let resize = self.windows[idx].resize;
+            if let Some(next_window) = self.windows.get_mut(idx + 1) {
+                next_window.resize = resize;
+            }
+        }
+
+        if self.zoomed == Some(window.hwnd) {
+            self.zoomed = None;
+        }
+
+        if self.master == Some(window.hwnd) {
+            self.master = None;
+        }
+
+        self.windows.remove(idx);
+        self.focus_history.retain(|hwnd| *hwnd != window.hwnd);
+        for occupants in &mut self.occupants {
+            occupants.retain(|hwnd| *hwnd != window.hwnd);
+        }
+        if tiled {
+            self.inherit_slot(slot);
+        }
+
+        let previous = idx.saturating_sub(1);
+        let find = |hwnd: HWND| self.windows.iter().position(|w| w.hwnd == hwnd);
+
+        match self.focus_after_close {
+            FocusAfterClose::Previous => previous,
+            FocusAfterClose::Next => idx.min(self.windows.len().saturating_sub(1)),
+            FocusAfterClose::Recent => self
+                .focus_history
+                .iter()
+                .find_map(|hwnd| find(*hwnd))
+                .unwrap_or(previous),
+            FocusAfterClose::Master => self.master.and_then(find).unwrap_or(0),
+        }
+    }
+
+    /// Moves the window picked by `space_inheritance` into the tiled position
+    /// `slot`, left empty by a window that has been closed. Resize adjustments
+    /// stay where they were because they belong to the position in the layout.
+    fn inherit_slot(&mut self, slot: usize) {
+        let tiled: Vec<usize> = self
+            .windows
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| w.should_tile())
+            .map(|(i, _)| i)
+            .collect();
+
+        // The closed window was the last one, whose space the layouts already
+        // give to the window before it
+        let last = slot >= tiled.len();
+        let target = slot.min(tiled.len().saturating_sub(1));
+
+        let heir = match self.space_inheritance {
+            SpaceInheritance::Next => return,
+            SpaceInheritance::Previous if last || slot == 0 => return,
+            SpaceInheritance::Previous => slot - 1,
+            SpaceInheritance::Largest => {
+                let area = |i: &usize| {
+                    let rect = self.window_rect(&self.windows[tiled[*i]]);
+                    rect.width * rect.height
+                };
+
+                match (0..tiled.len()).max_by_key(area) {
+                    Some(largest) => largest,
+                    None => return,
+                }
+            }
+        };
+
+        if heir == target || tiled[heir].min(tiled[target]) < self.first_free_idx() {
+            return;
+        }
+
+        let resizes: Vec<Option<Rect>> = self.windows.iter().map(|w| w.resize).collect();
+        let window = self.windows.remove(tiled[heir]);
+        self.windows.insert(tiled[target], window);
+
+        for (window, resize) in self.windows.iter_mut().zip(resizes) {
+            window.resize = resize;
+        }
+    }
+
+    /// Swaps the windows at `idx` and `new_idx`, leaving resize adjustments
+    /// where they were because they belong to the position in the layout. The
+    /// master window stays where it is.
+    pub fn swap_windows(&mut self, idx: usize, new_idx: usize) {
+        if idx.min(new_idx) < self.first_free_idx() {
+            return;
+        }
+
+        self.windows.swap(idx, new_idx);
+
+        let resize = self.windows[idx].resize;
+        self.windows[idx].resize = self.windows[new_idx].resize;
+        self.windows[new_idx].resize = resize;
+    }
+
+    /// Remembers which window is in each position for `cycle_tile_occupants`
+    fn record_occupants(&mut self) {
+        if self.occupants.len() < self.windows.len() {
+            self.occupants
+                .resize_with(self.windows.len(), VecDeque::new);
+        }
+
+        for (window, occupants) in self.windows.iter().zip(self.occupants.iter_mut()) {
+            if occupants.front() == Some(&window.hwnd) {
+                continue;
+            }
+
+            occupants.retain(|hwnd| *hwnd != window.hwnd);
+            occupants.push_front(window.hwnd);
+            occupants.truncate(OCCUPANT_HISTORY);
+        }
+    }
+
+    /// Swaps a window that has been in the foreground window's position before
+    /// back into it. Cycling in the same direction goes further back through
+    /// the windows that have been there, rather than back and forth between
+    /// the last two.
+    pub fn cycle_tile_occupants(&mut self, direction: CycleDirection) {
+        let idx = match self.foreground_window.index(&self.windows) {
+            Some(idx) => idx,
+            None => return,
+        };
+
+        self.record_occupants();
+
+        let windows = &self.windows;
+        let occupants = &mut self.occupants[idx];
+        occupants.retain(|hwnd| windows.iter().any(|w| w.hwnd == *hwnd));
+        if occupants.len() < 2 {
+            return;
+        }
+
+        match direction {
+            CycleDirection::Next => occupants.rotate_left(1),
+            CycleDirection::Previous => occupants.rotate_right(1),
+        }
+
+        let hwnd = occupants[0];
+        if let Some(new_idx) = self.windows.iter().position(|w| w.hwnd == hwnd) {
+            self.swap_windows(idx, new_idx);
+            self.calculate_layout();
+            self.apply_layout(Option::from(idx));
+            self.follow_focus_with_mouse(idx);
+        }
+    }
+
+    /// Floats the window at `idx` wherever it is now, instead of in the middle
+    /// of the display like `set_window_float`
+    pub fn float_in_place(&mut self, idx: usize) {
+        let window = &mut self.windows[idx];
+        if !window.tile {
+            return;
+        }
+
+        window.toggle_float();
+        let window = *window;
+
+        window.raise(self.insert_after(Layer::Floating));
+        self.calculate_layout();
+    }
+
+    /// Where `window` is placed when it is floated: the configured float size
+    /// for its exe, centred on the display
+    pub fn floating_rect(&self, window: &Window) -> Rect {
+        let size = if self.float_size_exes.is_empty() {
+            self.float_size
+        } else {
+            self.float_size_exes
+                .get(&window.exe_name())
+                .copied()
+                .unwrap_or(self.float_size)
+        };
+
+        let dimensions = self.get_dimensions();
+        let width = size.width.of(dimensions.width);
+        let height = size.height.of(dimensions.height);
+
+        Rect {
+            x: dimensions.x + ((dimensions.width - width) / 2),
+            y: dimensions.y + ((dimensions.height - height) / 2),
+            width,
+            height,
+        }
+    }
+
+    /// Draws the foreground window bigger than its tile and over the top of
+    /// its neighbours, or puts it back if it is already zoomed. The layout
+    /// itself stays as it is.
+    pub fn toggle_zoom(&mut self) {
+        self.get_foreground_window();
+        let window = self.foreground_window;
+
+        if self.zoomed == Some(window.hwnd) {
+            self.zoomed = None;
+        } else if self.windows.contains(&window) && window.should_tile() {
+            self.zoomed = Option::from(window.hwnd);
+        } else {
+            return;
+        }
+
+        self.apply_layout(None);
+    }
+
+    /// Floats every window on the display where it is, remembering which were
+    /// tiled for `tile_all`
+    pub fn float_all(&mut self) {
+        if self.floated_all.is_none() {
+            let tiled = self.windows.iter().filter(|w| w.tile).map(|w| w.hwnd);
+            self.floated_all = Option::from(tiled.collect::<Vec<HWND>>());
+        }
+
+        for window in &mut self.windows {
+            window.tile = false;
+        }
+
+        self.zoomed = None;
+        self.calculate_layout();
+        self.apply_layout(None);
+    }
+
+    /// Tiles the windows that were tiled before `float_all`, or every window
+    /// if nothing was floated that way
+    pub fn tile_all(&mut self) {
+        let floated_all = self.floated_all.take();
+
+        for window in &mut self.windows {
+            window.tile = match &floated_all {
+                Some(tiled) => window.tile || tiled.contains(&window.hwnd),
+                None => true,
+            };
+        }
+
+        self.calculate_layout();
+        self.apply_layout(None);
+    }
+
+    /// Takes on the settings in `desired` that differ from the current ones,
+    /// returning whether there were any
+    pub fn apply_desired(&mut self, desired: DesiredDisplay) -> bool {
+        let mut changed = false;
+
+        if let Some(layout) = desired.layout {
+            if layout != self.chosen_layout.unwrap_or(self.layout) {
+                // Layouts should always start in a balanced state
+                for window in self.windows.iter_mut() {
+                    window.resize = None
+                }
+
+                self.set_layout(layout);
+                changed = true;
+            }
+        }
+
+        if let Some(gap) = desired.inner_gap.filter(|gap| *gap != self.inner_gap) {
+            self.inner_gap = gap;
+            changed = true;
+        }
+
+        if let Some(gap) = desired.outer_gap.filter(|gap| *gap != self.outer_gap) {
+            self.outer_gap = gap;
+            changed = true;
+        }
+
+        if let Some(columns) = desired.columns.filter(|columns| *columns != self.columns) {
+            self.columns = columns;
+            changed = true;
+        }
+
+        if let Some(rows) = desired.rows.filter(|rows| *rows != self.rows) {
+            self.rows = rows;
+            changed = true;
+        }
+
+        changed
+    }
+
+    /// Index of the window in the tile under the mouse cursor, if any
+    fn tile_under_cursor(&self) -> Option<usize> {
+        let cursor_pos: POINT = unsafe {
+            let mut cursor_pos: POINT = mem::zeroed();
+            GetCursorPos(&mut cursor_pos);
+            cursor_pos
+        };
+
+        self.tile_at((cursor_pos.x, cursor_pos.y))
+    }
+
+    /// Index in `windows` of the window tiled at `point`, if any. Floating
+    /// windows have no layout position, so they are skipped over.
+    fn tile_at(&self, point: (i32, i32)) -> Option<usize> {
+        self.layout_dimensions
+            .iter()
+            .position(|rect| rect.contains_point(point))
+            .and_then(|position| self.tiled_window_idx(position))
+    }
+
+    /// Index of the first position that windows can be moved into, which is
+    /// after the master window if the display has one
+    pub fn first_free_idx(&self) -> usize {
+        match (self.master, self.windows.first()) {
+            (Some(master), Some(first)) if first.hwnd == master => 1,
+            _ => 0,
+        }
+    }
+
+    /// Makes the foreground window the master of the display, or stops
+    /// reserving the first position if it already is
+    pub fn toggle_master(&mut self) {
+        self.get_foreground_window();
+        let window = self.foreground_window;
+
+        if self.master == Some(window.hwnd) {
+            self.master = None;
+            return;
+        }
+
+        let idx = match window.index(&self.windows) {
+            Some(idx) => idx,
+            None => return,
+        };
+
+        let window = self.windows.remove(idx);
+        self.windows.insert(0, window);
+        self.master = Option::from(window.hwnd);
+
+        self.calculate_layout();
+        self.apply_layout(Option::from(0));
+    }
+
+    pub fn set_monocle(&mut self, monocle: bool) {
+        if monocle == matches!(self.layout, Layout::Monocle) {
+            return;
+        }
+
+        if monocle {
+            self.set_layout(Layout::Monocle);
+            self.calculate_layout();
+            self.apply_layout(None);
+            return;
+        }
+
+        self.set_layout(self.previous_layout);
+        self.calculate_layout();
+        self.apply_layout(None);
+
+        let idx = self.get_foreground_window_index();
+        if let Some(window) = self.windows.get(idx) {
+            // If we have monocle'd a floating window, we want to restore it to the
+            // default floating position when toggling off monocle
+            if !window.tile {
+                let rect = self.floating_rect(window);
+                window.set_pos(rect, self.insert_after(Layer::Floating), None);
+                self.warp_cursor_to(window, rect, false);
+            }
+        }
+    }
+
+    /// Floats or tiles the foreground window
+    pub fn set_float(&mut self, float: bool) {
+        if let Some(idx) = self.foreground_window.index(&self.windows) {
+            self.set_window_float(idx, float);
+        }
+    }
+
+    /// Floats or tiles the window at `idx`
+    pub fn set_window_float(&mut self, idx: usize, float: bool) {
+        if self.windows[idx].tile != float {
+            return;
+        }
+
+        self.windows[idx].toggle_float();
+        let window = self.windows[idx];
+        self.calculate_layout();
+        self.apply_layout(None);
+
+        // Centre the window if we have disabled tiling
+        if float {
+            let rect = self.floating_rect(&window);
+            window.set_pos(rect, self.insert_after(Layer::Floating), None);
+            self.warp_cursor_to(&window, rect, false);
+        } else if let Some(rect) = self.window_layout_rect(idx) {
+            // Make sure the mouse cursor goes back once we reenable tiling
+            self.warp_cursor_to(&window, rect, false);
+        }
     }
 
-    pub fn get_foreground_window_index(&mut self) -> usize {
-        let mut idx = 0;
+    /// Switches layout, remembering the current one when entering Monocle so
+    /// that it can be toggled back to
+    pub fn set_layout(&mut self, layout: Layout) {
+        if matches!(layout, Layout::Monocle) && !matches!(self.layout, Layout::Monocle) {
+            self.previous_layout = self.layout;
+        }
+
+        self.layout = layout;
+        self.chosen_layout = None;
+    }
+
+    /// Switches to the layout required by the layout rules matching the
+    /// windows on the display, or back to the chosen layout once none do
+    fn apply_layout_rules(&mut self) {
+        if self.layout_rules.is_empty() && self.chosen_layout.is_none() {
+            return;
+        }
+
+        let exes: Vec<String> = self.windows.iter().map(Window::exe_name).collect();
+        let rules: Vec<&LayoutRule> = self
+            .layout_rules
+            .iter()
+            .filter(|rule| exes.contains(&rule.exe))
+            .collect();
+
+        let chosen = self.chosen_layout.unwrap_or(self.layout);
+        let layout = ruled_layout(chosen, &rules);
 
-        for (i, w) in self.windows.iter().enumerate() {
-            if self.foreground_window.hwnd == w.hwnd {
-                idx = i;
-                break;
+        if layout != self.layout {
+            info!("layout rules switched a display to {}", layout);
+
+            if matches!(layout, Layout::Monocle) && !matches!(self.layout, Layout::Monocle) {
+                self.previous_layout = self.layout;
             }
         }
 
-        idx
+        self.chosen_layout = Some(chosen).filter(|chosen| *chosen != layout);
+        self.layout = layout;
+    }
+
+    /// Where a window in `layer` goes in the z-order when it is moved, None to
+    /// leave it where it is. Every window yatta moves should be placed with
+    /// this.
+    pub fn insert_after(&self, layer: Layer) -> Option<HWND> {
+        match (self.z_order, layer) {
+            (_, Layer::Raised) | (ZOrder::Layered, Layer::Floating) => Option::from(HWND_TOP),
+            (ZOrder::Layered, Layer::Tiled) => Option::from(HWND_BOTTOM),
+            (ZOrder::Preserve, _) => None,
+        }
+    }
+
+    pub fn get_foreground_window(&mut self) {
+        self.foreground_window = Window::foreground();
+    }
+
+    pub fn get_foreground_window_index(&self) -> usize {
+        self.foreground_window.index(&self.windows).unwrap_or(0)
     }
 
     pub fn set_cursor_pos_to_centre(&self) {
@@ -88,9 +879,27 @@ impl Display {
     }
 
     pub fn follow_focus_with_mouse(&mut self, idx: usize) {
-        if let Some(window) = self.windows.get(idx) {
-            window.set_cursor_pos(self.layout_dimensions[idx]);
+        self.warp_cursor_to_idx(idx, false);
+    }
+
+    fn warp_cursor_to_idx(&self, idx: usize, display_change: bool) {
+        if let (Some(window), Some(rect)) = (self.windows.get(idx), self.window_layout_rect(idx)) {
+            self.warp_cursor_to(window, rect, display_change);
+        };
+    }
+
+    /// Moves the cursor onto `window` if the cursor warp setting allows it,
+    /// `display_change` being whether the focus came from another display
+    fn warp_cursor_to(&self, window: &Window, rect: Rect, display_change: bool) {
+        let warp = match self.cursor_warp {
+            CursorWarp::Always => true,
+            CursorWarp::DisplayChange => display_change,
+            CursorWarp::Never => false,
         };
+
+        if warp {
+            window.set_cursor_pos(rect, self.dimensions);
+        }
     }
 
     pub fn resize_window(&mut self, edge: ResizeEdge, sizing: Sizing, step: Option<i32>) {
@@ -139,7 +948,6 @@ impl Display {
                 self.windows.len(),
                 self.get_dimensions(),
                 vertical,
-                self.gaps,
                 vec![],
             )[idx];
 
@@ -229,114 +1037,123 @@ impl Display {
         }
     }
 
-    pub fn window_op_up(&mut self, op: DirectionOperation) {
+    /// Finds the tiled window next to the foreground window in a direction by
+    /// comparing layout positions, which works the same way for every layout
+    pub fn window_op_in_direction(
+        &mut self,
+        direction: OperationDirection,
+        op: DirectionOperation,
+    ) {
+        // Floating windows have no position in the layout, so keep track of which
+        // window each layout position belongs to
+        let tiled: Vec<usize> = self
+            .windows
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| w.should_tile())
+            .map(|(i, _)| i)
+            .collect();
+
         let idx = self.get_foreground_window_index();
-        let can_move = match self.layout {
-            Layout::BSPV => self.windows.len() > 2 && idx != 0 && idx != 1,
-            Layout::BSPH => self.windows.len() > 1 && idx != 0,
-            Layout::Columns | Layout::Monocle => false,
-            Layout::Rows => idx != 0,
+        let position = match tiled.iter().position(|i| *i == idx) {
+            Some(position) => position,
+            None => return,
         };
 
-        if can_move {
-            let new_idx = match self.layout {
-                Layout::BSPV => {
-                    if idx % 2 == 0 {
-                        idx - 1
-                    } else {
-                        idx - 2
-                    }
-                }
-
-                Layout::BSPH => {
-                    if idx % 2 == 0 {
-                        idx - 2
-                    } else {
-                        idx - 1
-                    }
-                }
-                Layout::Columns | Layout::Monocle => unreachable!(),
-                Layout::Rows => idx - 1,
-            };
+        let rects = &self.layout_dimensions[..tiled.len().min(self.layout_dimensions.len())];
+        let origin = match rects.get(position) {
+            Some(rect) => *rect,
+            None => return,
+        };
 
-            op.handle(self, idx, new_idx);
+        if let Some(new_position) = nearest_in_direction(origin, direction, rects) {
+            op.handle(self, idx, tiled[new_position]);
         }
     }
 
-    pub fn window_op_down(&mut self, op: DirectionOperation) {
-        let idx = self.get_foreground_window_index();
-        let len = self.windows.len();
-
-        let can_move = match self.layout {
-            Layout::BSPV => len > 2 && idx != len - 1 && idx % 2 != 0,
-            Layout::BSPH => self.windows.len() > 1 && idx % 2 == 0,
-            Layout::Columns | Layout::Monocle => false,
-            Layout::Rows => idx != self.windows.len() - 1,
+    /// Trades places with the nearest window in a direction on the other side
+    /// of the float/tiled boundary: the floating window takes over the tile
+    /// and the tiled window floats where the floating one was
+    pub fn swap_with_float(&mut self, direction: OperationDirection) {
+        let idx = match self.foreground_window.index(&self.windows) {
+            Some(idx) => idx,
+            None => return,
         };
 
-        if can_move {
-            let new_idx = match self.layout {
-                Layout::BSPV | Layout::BSPH | Layout::Rows => idx + 1,
-                Layout::Columns | Layout::Monocle => unreachable!(),
+        // Windows floated by a rule or given up on can't be tiled
+        let swappable = |w: &Window| !w.float_rule && !w.is_unmovable();
+        if !swappable(&self.windows[idx]) {
+            return;
+        }
+
+        let tiled = self.windows[idx].should_tile();
+        let candidates: Vec<usize> = self
+            .windows
+            .iter()
+            .enumerate()
+            .filter(|(i, w)| *i != idx && swappable(w) && w.should_tile() != tiled)
+            .map(|(i, _)| i)
+            .collect();
+
+        let rects: Vec<Rect> = candidates
+            .iter()
+            .map(|i| self.window_rect(&self.windows[*i]))
+            .collect();
+        let other =
+            match nearest_in_direction(self.window_rect(&self.windows[idx]), direction, &rects) {
+                Some(position) => candidates[position],
+                None => return,
             };
 
-            op.handle(self, idx, new_idx);
+        if idx.min(other) < self.first_free_idx() {
+            return;
         }
-    }
 
-    pub fn window_op_left(&mut self, op: DirectionOperation) {
-        let idx = self.get_foreground_window_index();
-        let can_move = match self.layout {
-            Layout::BSPV => self.windows.len() > 1 && idx != 0,
-            Layout::BSPH => self.windows.len() > 2 && idx != 0 && idx != 1,
-            Layout::Columns => idx != 0,
-            Layout::Rows | Layout::Monocle => false,
-        };
+        let (tiled_idx, floating_idx) = if tiled { (idx, other) } else { (other, idx) };
+        let floating_rect = self.windows[floating_idx].rect();
 
-        if can_move {
-            let new_idx = match self.layout {
-                Layout::BSPV => {
-                    if idx % 2 == 0 {
-                        idx - 2
-                    } else {
-                        idx - 1
-                    }
-                }
+        self.swap_windows(tiled_idx, floating_idx);
+        self.windows[tiled_idx].toggle_float();
+        self.windows[floating_idx].toggle_float();
 
-                Layout::BSPH => {
-                    if idx % 2 == 0 {
-                        idx - 1
-                    } else {
-                        idx - 2
-                    }
-                }
+        self.calculate_layout();
+        self.apply_layout(None);
 
-                Layout::Columns => idx - 1,
-                Layout::Rows | Layout::Monocle => unreachable!(),
-            };
+        self.windows[floating_idx].set_pos(floating_rect, self.insert_after(Layer::Floating), None);
+    }
 
-            op.handle(self, idx, new_idx);
+    /// Focuses the next or previous floating window in window list order,
+    /// starting from the first or last if a floating window isn't focused
+    pub fn focus_float(&mut self, direction: CycleDirection) {
+        let floating: Vec<usize> = self
+            .windows
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| !w.should_tile())
+            .map(|(i, _)| i)
+            .collect();
+
+        if floating.is_empty() {
+            return;
         }
-    }
 
-    pub fn window_op_right(&mut self, op: DirectionOperation) {
-        let idx = self.get_foreground_window_index();
+        let current = self
+            .foreground_window
+            .index(&self.windows)
+            .and_then(|idx| floating.iter().position(|i| *i == idx));
 
-        let can_move = match self.layout {
-            Layout::BSPV => self.windows.len() > 1 && idx % 2 == 0,
-            Layout::BSPH => self.windows.len() > 2 && idx % 2 != 0 && idx != self.windows.len() - 1,
-            Layout::Columns => idx != self.windows.len() - 1,
-            Layout::Rows | Layout::Monocle => false,
+        let position = match (direction, current) {
+            (CycleDirection::Next, Some(position)) => (position + 1) % floating.len(),
+            (CycleDirection::Previous, Some(position)) => {
+                (position + floating.len() - 1) % floating.len()
+            }
+            (CycleDirection::Next, None) => 0,
+            (CycleDirection::Previous, None) => floating.len() - 1,
         };
 
-        if can_move {
-            let new_idx = match self.layout {
-                Layout::BSPV | Layout::BSPH | Layout::Columns => idx + 1,
-                Layout::Rows | Layout::Monocle => unreachable!(),
-            };
-
-            op.handle(self, idx, new_idx);
-        }
+        let window = self.windows[floating[position]];
+        window.set_foreground();
+        self.warp_cursor_to(&window, self.window_rect(&window), false);
     }
 
     pub fn window_op_next(&mut self, op: DirectionOperation) {
@@ -369,9 +1186,58 @@ impl Display {
         }
     }
 
+    /// Focuses the nearest window in a direction based on where windows
+    /// actually are on screen, so that floating windows can be reached as
+    /// well as tiled ones. Returns false if there was no window to focus.
+    pub fn focus_window_in_direction(&mut self, direction: OperationDirection, wrap: bool) -> bool {
+        let idx = self.get_foreground_window_index();
+        let rects: Vec<Rect> = self.windows.iter().map(|w| self.window_rect(w)).collect();
+
+        let origin = match rects.get(idx) {
+            Some(rect) => *rect,
+            None => return false,
+        };
+
+        let mut new_idx = nearest_in_direction(origin, direction, &rects);
+
+        // Start looking again from the opposite edge of the display
+        if new_idx.is_none() && wrap {
+            let origin = beyond_edge(origin, self.dimensions, direction);
+            new_idx = nearest_in_direction(origin, direction, &rects);
+        }
+
+        self.focus_window_at(new_idx, &rects, false)
+    }
+
+    /// Focuses the window that is nearest to `origin` when coming into this
+    /// display in `direction`. Returns false if there was no window to focus.
+    pub fn focus_window_from_edge(&mut self, origin: Rect, direction: OperationDirection) -> bool {
+        let rects: Vec<Rect> = self.windows.iter().map(|w| self.window_rect(w)).collect();
+        let origin = beyond_edge(origin, self.dimensions, direction);
+        let new_idx = nearest_in_direction(origin, direction, &rects);
+
+        self.focus_window_at(new_idx, &rects, true)
+    }
+
+    fn focus_window_at(&self, idx: Option<usize>, rects: &[Rect], display_change: bool) -> bool {
+        match idx {
+            Some(idx) => {
+                let window = self.windows[idx];
+                window.set_foreground();
+                self.warp_cursor_to(&window, rects[idx], display_change);
+                true
+            }
+            None => false,
+        }
+    }
+
     fn calculate_resize_adjustments(&self) -> Vec<Option<Rect>> {
-        let windows: Vec<&Window> = self.windows.iter().filter(|x| x.should_tile()).collect();
-        let resize_dimensions: Vec<Option<Rect>> = windows.iter().map(|x| x.resize).collect();
+        let resize_dimensions: Vec<Option<Rect>> = self
+            .windows
+            .iter()
+            .filter(|x| x.should_tile())
+            .map(|x| x.resize)
+            .collect();
         let mut resize_adjustments = resize_dimensions.clone();
 
         for (i, opt) in resize_dimensions.iter().enumerate() {
@@ -454,109 +1320,322 @@ impl Display {
     }
 
     pub fn calculate_layout(&mut self) {
+        self.apply_layout_rules();
+
         let len = self.windows.iter().filter(|x| x.should_tile()).count();
+        let single_tile = len <= 1 || matches!(self.layout, Layout::Monocle);
+        let monocle = matches!(self.layout, Layout::Monocle);
+        let area = if (self.smart_gaps && single_tile) || (monocle && !self.monocle_outer_gap) {
+            self.dimensions
+        } else {
+            self.get_dimensions()
+        };
 
+        // Layouts tile the area edge to edge, gaps between tiles are added afterwards
         match self.layout {
             Layout::Monocle => {
-                self.layout_dimensions = bsp(0, 1, self.get_dimensions(), 1, self.gaps, vec![]);
+                self.layout_dimensions = vec![centre_in(area, self.monocle_width)];
             }
             Layout::BSPV => {
                 let resize_adjustments = self.calculate_resize_adjustments();
-                self.layout_dimensions = bsp(
-                    0,
-                    len,
-                    self.get_dimensions(),
-                    1,
-                    self.gaps,
-                    resize_adjustments,
-                );
+                self.layout_dimensions = bsp(0, len, area, 1, resize_adjustments);
             }
             Layout::BSPH => {
                 let resize_adjustments = self.calculate_resize_adjustments();
-                self.layout_dimensions = bsp(
-                    0,
-                    len,
-                    self.get_dimensions(),
-                    0,
-                    self.gaps,
-                    resize_adjustments,
-                );
+                self.layout_dimensions = bsp(0, len, area, 0, resize_adjustments);
             }
             Layout::Columns => {
-                let width_f = self.get_dimensions().width as f32 / len as f32;
-                let width = width_f.floor() as i32;
-
-                let mut x = 0;
-                let mut layouts: Vec<Rect> = vec![];
-                for _ in &self.windows {
-                    layouts.push(Rect {
-                        x:      (self.get_dimensions().x + x) + self.gaps,
-                        y:      (self.get_dimensions().y) + self.gaps,
-                        width:  width - (self.gaps * 2),
-                        height: self.get_dimensions().height - (self.gaps * 2),
-                    });
-                    x += width;
-                }
-                self.layout_dimensions = layouts
+                self.layout_dimensions = lanes(area, len, self.columns, true);
             }
             Layout::Rows => {
-                let height_f = self.get_dimensions().height as f32 / len as f32;
-                let height = height_f.floor() as i32;
+                self.layout_dimensions = lanes(area, len, self.rows, false);
+            }
+            Layout::Centred => {
+                self.layout_dimensions = centred(area, len, self.centred_width);
+            }
+        }
+
+        // Without a gap, tiles overlap by the Windows 11 border so that it is
+        // drawn once between them rather than twice
+        let inner_gap = if self.windows_11_compat && self.inner_gap == 0 {
+            -WINDOWS_11_BORDER
+        } else {
+            self.inner_gap
+        };
+
+        // Resize adjustments and gaps can push rects off the display or give them
+        // negative sizes, neither of which SetWindowPos handles gracefully
+        let bounds = self.dimensions;
+        for rect in &mut self.layout_dimensions {
+            *rect = inset_inner_edges(*rect, area, inner_gap).clamp_to(bounds);
+        }
+    }
+
+    /// Hides or minimizes every window other than the monocle window, depending
+    /// on `monocle_others`
+    fn hide_monocle_others(&mut self) {
+        let foreground = self.foreground_window.hwnd;
+        let others = self.monocle_others;
 
-                let mut y = 0;
-                let mut layouts: Vec<Rect> = vec![];
-                for _ in &self.windows {
-                    layouts.push(Rect {
-                        x:      self.get_dimensions().x + self.gaps,
-                        y:      self.get_dimensions().y + y + self.gaps,
-                        width:  self.get_dimensions().width - (self.gaps * 2),
-                        height: height - (self.gaps * 2),
-                    });
-                    y += height;
+        // If something we don't manage has focus there is no monocle window
+        if others == MonocleOthers::Visible || !self.windows.iter().any(|w| w.hwnd == foreground) {
+            return;
+        }
+
+        for window in &mut self.windows {
+            if window.hwnd == foreground {
+                if window.hidden {
+                    window.hidden = false;
+                    window.show();
+                }
+            } else if !window.hidden {
+                // Set this first so that the hide event for it can be ignored
+                window.hidden = true;
+
+                match others {
+                    MonocleOthers::Minimize => window.minimize(),
+                    MonocleOthers::Hide => window.hide(),
+                    MonocleOthers::Visible => {}
                 }
-                self.layout_dimensions = layouts
             }
         }
     }
 
+    fn show_hidden_windows(&mut self) {
+        for window in &mut self.windows {
+            if window.hidden {
+                window.hidden = false;
+                window.show();
+            }
+        }
+    }
+
+    /// Looks up the calculated rect of a layout position, logging instead of
+    /// panicking when the layout and the window list are out of sync
+    pub fn layout_rect(&self, position: usize) -> Option<Rect> {
+        let rect = self.layout_dimensions.get(position).copied();
+
+        if rect.is_none() {
+            error!(
+                "no layout rect for position {} ({} positions calculated)",
+                position,
+                self.layout_dimensions.len()
+            );
+        }
+
+        rect
+    }
+
+    /// The layout position of the window at `idx`, which only counts the tiled
+    /// windows before it, or None if the window isn't tiled
+    pub fn tiled_position(&self, idx: usize) -> Option<usize> {
+        if !self.windows.get(idx)?.should_tile() {
+            return None;
+        }
+
+        Option::from(
+            self.windows[..idx]
+                .iter()
+                .filter(|w| w.should_tile())
+                .count(),
+        )
+    }
+
+    /// The index in `windows` of the tiled window at layout `position`
+    pub fn tiled_window_idx(&self, position: usize) -> Option<usize> {
+        self.windows
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| w.should_tile())
+            .nth(position)
+            .map(|(idx, _)| idx)
+    }
+
+    /// Where the window at `idx` is tiled, or None if it isn't tiled
+    pub fn window_layout_rect(&self, idx: usize) -> Option<Rect> {
+        let position = self.tiled_position(idx)?;
+
+        // Every tiled window shares the one Monocle position
+        if matches!(self.layout, Layout::Monocle) {
+            return self.layout_rect(0);
+        }
+
+        self.layout_rect(position)
+    }
+
+    /// Where `window` is, for comparing against layout positions and other
+    /// windows
+    pub fn window_rect(&self, window: &Window) -> Rect {
+        if self.compensate_frames {
+            window.frame_rect()
+        } else {
+            window.rect()
+        }
+    }
+
+    /// Where to put `window` for it to end up in the layout position `rect`
+    fn placement_rect(&self, window: &Window, rect: Rect) -> Rect {
+        if self.compensate_frames {
+            window.compensate_frame(rect)
+        } else {
+            rect
+        }
+    }
+
     pub fn apply_layout(&mut self, new_focus: Option<usize>) {
+        // Moving windows around mid-click can swallow clicks on their title bar
+        // buttons, so wait until the button is released
+        if self.in_transaction || (self.defer_while_clicking && mouse_button_held()) {
+            self.layout_pending = true;
+            return;
+        }
+
         if let Layout::Monocle = self.layout {
             self.get_foreground_window();
-            self.foreground_window.set_pos(
-                self.layout_dimensions[0],
-                Option::from(HWND_NOTOPMOST),
-                None,
-            );
+            if let Some(rect) = self.layout_rect(0) {
+                let rect = self.placement_rect(&self.foreground_window, rect);
+                self.foreground_window
+                    .set_pos(rect, self.insert_after(Layer::Raised), None);
+            }
+
+            self.hide_monocle_others();
 
             return;
         }
 
+        self.show_hidden_windows();
+
         let mut skipped = 0;
-        for (i, w) in self.windows.iter().enumerate() {
+        let mut gave_up = false;
+        for i in 0..self.windows.len() {
+            let w = self.windows[i];
             if w.should_tile() {
-                if let Some(new_idx) = new_focus {
+                let rect = match self.layout_rect(i - skipped) {
+                    Some(rect) => rect,
+                    None => continue,
+                };
+
+                let moved = match new_focus {
                     // Make sure this is focused
-                    if i == new_idx {
-                        w.set_pos(
-                            self.layout_dimensions[new_idx],
-                            None,
-                            Option::from(SWP_NOMOVE | SWP_NOSIZE),
+                    Some(new_idx) if i == new_idx => w.set_pos(
+                        rect,
+                        self.insert_after(Layer::Tiled),
+                        Option::from(SWP_NOMOVE | SWP_NOSIZE),
+                    ),
+                    _ if self.zoomed == Some(w.hwnd) => w.set_pos(
+                        self.placement_rect(&w, zoom(rect, self.zoom_percent, self.dimensions)),
+                        self.insert_after(Layer::Raised),
+                        None,
+                    ),
+                    _ => w.set_pos(
+                        self.placement_rect(&w, rect),
+                        self.insert_after(Layer::Tiled),
+                        None,
+                    ),
+                };
+
+                if self.square_corners {
+                    w.set_square_corners(true);
+                }
+
+                let window = &mut self.windows[i];
+                if moved {
+                    window.pos_failures = 0;
+                } else {
+                    window.pos_failures += 1;
+                    if window.is_unmovable() {
+                        error!(
+                            "floating {} ({}) as it can't be moved",
+                            w.exe_name(),
+                            w.hwnd.0
                         );
-                    } else {
-                        w.set_pos(self.layout_dimensions[i - skipped], None, None)
+                        gave_up = true;
                     }
-                } else {
-                    w.set_pos(self.layout_dimensions[i - skipped], None, None)
                 }
             } else {
+                if self.square_corners {
+                    w.set_square_corners(false);
+                }
+
                 skipped += 1
             }
         }
+
+        // The windows that were given up on aren't tiled any more, so the rest of
+        // the layout can take over the space they were in
+        if gave_up {
+            self.calculate_layout();
+            self.apply_layout(new_focus);
+            return;
+        }
+
+        self.restack();
+        self.record_occupants();
+    }
+
+    /// The band of the z-order that `window` belongs in
+    pub fn layer(&self, window: &Window) -> Layer {
+        if self.zoomed == Some(window.hwnd) {
+            Layer::Raised
+        } else if window.should_tile() {
+            Layer::Tiled
+        } else {
+            Layer::Floating
+        }
+    }
+
+    /// The windows above the tiled band, bottom first, to bring back up after
+    /// tiles have been moved in case a tile ended up in front of them. Nothing
+    /// is restacked when the z-order is preserved.
+    fn restack_order(&self) -> Vec<Window> {
+        if self.z_order == ZOrder::Preserve {
+            return vec![];
+        }
+
+        let mut windows: Vec<Window> = self
+            .windows
+            .iter()
+            .filter(|w| self.layer(w) != Layer::Tiled)
+            .copied()
+            .collect();
+
+        windows.sort_by_key(|w| self.layer(w) == Layer::Raised);
+        windows
+    }
+
+    fn restack(&self) {
+        for window in self.restack_order() {
+            window.raise(self.insert_after(self.layer(&window)));
+        }
     }
 }
 
 impl Desktop {
     pub fn get_active_display_idx(&self) -> usize {
+        if self.active_display == ActiveDisplay::ForegroundWindow {
+            if let Some(idx) = self.get_foreground_display_idx() {
+                return idx;
+            }
+        }
+
+        self.get_cursor_display_idx()
+    }
+
+    fn get_foreground_display_idx(&self) -> Option<usize> {
+        let foreground = Window::foreground();
+
+        // The desktop window doesn't tell us anything about which display the user
+        // is looking at
+        if foreground.hwnd.0 == 0 || foreground.hwnd == Window::shell().hwnd {
+            return None;
+        }
+
+        let hmonitor = unsafe { MonitorFromWindow(foreground.hwnd, MONITOR_DEFAULTTONULL) };
+
+        self.displays.iter().position(|d| d.hmonitor == hmonitor)
+    }
+
+    fn get_cursor_display_idx(&self) -> usize {
         let active_display = unsafe {
             let mut cursor_pos: POINT = mem::zeroed();
             GetCursorPos(&mut cursor_pos);
@@ -573,38 +1652,270 @@ impl Desktop {
         0
     }
 
+    /// Resolves a display number (starting from 1) to an index into `displays`
+    pub fn get_display_idx(&self, number: usize) -> Option<usize> {
+        if number > 0 && number <= self.displays.len() {
+            Option::from(number - 1)
+        } else {
+            None
+        }
+    }
+
+    /// Swaps in a freshly enumerated set of displays in one go, keeping the
+    /// current ones if enumeration comes back empty
     pub fn enumerate_display_monitors(&mut self) {
-        self.displays.clear();
+        let displays = display_monitors();
+        if displays.is_empty() {
+            error!("could not enumerate any displays, keeping the current ones");
+            return;
+        }
 
-        unsafe {
-            EnumDisplayMonitors(
-                HDC(0),
-                std::ptr::null_mut(),
-                Some(enum_display_monitor),
-                LPARAM(&mut self.displays as *mut Vec<Display> as isize),
-            );
+        self.displays = displays;
+    }
+
+    /// Applies the config to every display, using the settings of the first
+    /// profile that matches the connected displays if there is one
+    pub fn configure_displays(&mut self, config: &Config) {
+        let config = self.profile_config(config);
+
+        for (i, display) in self.displays.iter_mut().enumerate() {
+            display.configure(&config, i);
+            display.in_transaction = self.transaction.is_some();
+            display.get_foreground_window()
         }
     }
 
-    pub fn get_visible_windows(&mut self) {
-        let mut windows: Vec<Window> = vec![];
+    /// `config` with the settings of the profile matching the connected
+    /// displays in place, remembering which profile that is
+    fn profile_config(&mut self, config: &Config) -> Config {
+        let resolutions: Vec<String> = self.displays.iter().map(Display::resolution).collect();
+        let profile = config.profile(&resolutions);
 
-        unsafe {
-            EnumWindows(
-                Some(enum_window),
-                LPARAM(&mut windows as *mut Vec<Window> as isize),
-            );
+        self.profile = profile.map(|profile| profile.name.clone());
+
+        match profile {
+            Some(profile) => {
+                info!(
+                    "using the {} profile for displays {:?}",
+                    profile.name, resolutions
+                );
+                config.with_profile(profile)
+            }
+            None => config.clone(),
+        }
+    }
+
+    /// Picks up displays being connected, disconnected or changing resolution.
+    /// Windows keep to the display they are on, or move to the primary display
+    /// if theirs is gone.
+    pub fn reconfigure(&mut self, config: &Config) {
+        let displays = display_monitors();
+        if displays.is_empty() {
+            error!("could not enumerate any displays, keeping the current ones");
+            return;
+        }
+
+        self.reconnect(displays, config);
+        self.calculate_layouts();
+        if !self.paused {
+            self.apply_layouts(None);
+        }
+    }
+
+    /// Replaces the displays with the `connected` ones. Displays that were
+    /// already connected keep their windows and anything that was changed on
+    /// them at runtime, and are only configured again if the displays match
+    /// another profile now.
+    fn reconnect(&mut self, connected: Vec<Display>, config: &Config) {
+        let mut disconnected = mem::take(&mut self.displays);
+        let mut reconnected = vec![];
+
+        for display in connected {
+            match disconnected
+                .iter()
+                .position(|d| d.hmonitor == display.hmonitor)
+            {
+                Some(idx) => {
+                    let mut previous = disconnected.remove(idx);
+                    previous.dimensions = display.dimensions;
+                    self.displays.push(previous);
+                    reconnected.push(true);
+                }
+                None => {
+                    self.displays.push(display);
+                    reconnected.push(false);
+                }
+            }
         }
 
+        // Windows that have ended up on another display, for example because
+        // the resolution changed, are moved along with those of displays that
+        // are gone
+        let mut moved: Vec<Window> = disconnected
+            .into_iter()
+            .flat_map(|display| display.windows)
+            .collect();
+
         for display in &mut self.displays {
-            display.windows.clear();
+            for window in &mut display.windows {
+                window.hmonitor =
+                    unsafe { MonitorFromWindow(window.hwnd, MONITOR_DEFAULTTOPRIMARY) };
+            }
+
+            let elsewhere: Vec<Window> = display
+                .windows
+                .iter()
+                .filter(|window| window.hmonitor != display.hmonitor)
+                .copied()
+                .collect();
+
+            for window in &elsewhere {
+                display.unmanage_window(window);
+            }
+
+            moved.extend(elsewhere);
+        }
+
+        for mut window in moved {
+            window.hmonitor = unsafe { MonitorFromWindow(window.hwnd, MONITOR_DEFAULTTOPRIMARY) };
+
+            match self
+                .displays
+                .iter_mut()
+                .find(|display| display.hmonitor == window.hmonitor)
+            {
+                Some(display) => display.windows.push(window),
+                None => {
+                    window.hmonitor = self.displays[0].hmonitor;
+                    self.displays[0].windows.push(window);
+                }
+            }
+        }
+
+        let previous_profile = self.profile.clone();
+        let config = self.profile_config(config);
+        let profile_changed = self.profile != previous_profile;
+
+        for (i, display) in self.displays.iter_mut().enumerate() {
+            if profile_changed || !reconnected[i] {
+                display.configure(&config, i);
+                display.in_transaction = self.transaction.is_some();
+                display.get_foreground_window()
+            }
+        }
+    }
+
+    pub fn work_areas_changed(&self) -> bool {
+        self.displays
+            .iter()
+            .any(|display| display.work_area() != display.dimensions)
+    }
+
+    pub fn get_visible_windows(&mut self) {
+        let mut windows = open_windows();
+
+        // Of the windows of a process that only gets one tile, the biggest one wins
+        let mut largest: HashMap<u32, Window> = HashMap::new();
+        for window in &windows {
+            if self.exe_multi_window(&window.exe_name()) == MultiWindowBehaviour::Tile {
+                continue;
+            }
+
+            let (pid, _) = window.thread_process_id();
+            let area = |w: &Window| {
+                let rect = w.rect();
+                rect.width * rect.height
+            };
+
+            match largest.get(&pid) {
+                Some(current) if area(current) >= area(window) => {}
+                _ => {
+                    largest.insert(pid, *window);
+                }
+            }
+        }
+
+        windows.retain(|x| match largest.get(&x.thread_process_id().0) {
+            Some(winner) => winner == x,
+            None => true,
+        });
+
+        for window in windows.iter_mut() {
+            window.float_rule = window.matches_float_rule(&self.rules, &self.float_windows);
+        }
+
+        // Dialogs that are already open are only picked up if they are to be tiled,
+        // like windows that match a float rule
+        let dialog_behaviour = self.dialog_behaviour;
+        let rules = &self.rules;
 
+        windows.retain(|x| {
+            x.should_tile()
+                && !x.is_splash(rules)
+                && (dialog_behaviour == DialogBehaviour::Tile || !x.is_dialog())
+        });
+
+        for display in &mut self.displays {
             display.windows = windows
                 .iter()
-                .filter(|x| x.should_tile())
                 .filter(|x| x.hmonitor == display.hmonitor)
-                .map(|x| x.to_owned())
-                .collect::<Vec<Window>>();
+                .copied()
+                .collect();
+        }
+    }
+
+    fn focus_display_idx(&mut self, idx: usize) {
+        let click = self.focus_empty_display_with_click;
+        let target = self.displays[idx].borrow_mut();
+        let warp = self.warp_cursor_on_display_focus && target.cursor_warp != CursorWarp::Never;
+
+        if let Some(window) = target.windows.first() {
+            window.set_foreground();
+
+            if warp {
+                target.warp_cursor_to_idx(0, true)
+            }
+        } else {
+            if warp {
+                target.set_cursor_pos_to_centre();
+            }
+
+            // Clicking can dismiss menus or land on something unintended, so by default
+            // we just hand focus to the desktop window instead. A click without warping
+            // would land on whatever is under the cursor on the other display.
+            if click && warp {
+                send_left_click();
+            } else {
+                Window::shell().set_foreground();
+            }
+        }
+    }
+
+    pub fn focus_window_in_direction(&mut self, display_idx: usize, direction: OperationDirection) {
+        let wrap = self.focus_wrap == FocusWrap::SameDisplay;
+        let display = self.displays[display_idx].borrow_mut();
+
+        if display.focus_window_in_direction(direction, wrap)
+            || self.focus_wrap != FocusWrap::AdjacentDisplay
+        {
+            return;
+        }
+
+        // Carry on from the focused window, or from the whole display if it is empty
+        let idx = display.get_foreground_window_index();
+        let origin = match display.windows.get(idx) {
+            Some(window) => display.window_rect(window),
+            None => display.dimensions,
+        };
+
+        let display_rects: Vec<Rect> = self.displays.iter().map(|d| d.dimensions).collect();
+        let to = match nearest_in_direction(display_rects[display_idx], direction, &display_rects) {
+            Some(to) => to,
+            None => return,
+        };
+
+        if !self.displays[to].focus_window_from_edge(origin, direction) {
+            self.focus_display_idx(to);
         }
     }
 
@@ -629,15 +1940,7 @@ impl Desktop {
                 }
             };
 
-            let target = self.displays[to].borrow_mut();
-            if let Some(window) = target.windows.first() {
-                window.set_foreground();
-                target.follow_focus_with_mouse(0)
-            } else {
-                target.set_cursor_pos_to_centre();
-                let mut enigo = Enigo::new();
-                enigo.mouse_click(MouseButton::Left)
-            }
+            self.focus_display_idx(to);
         }
     }
 
@@ -647,15 +1950,7 @@ impl Desktop {
         if can_focus {
             let to = to - 1;
 
-            let target = self.displays[to].borrow_mut();
-            if let Some(window) = target.windows.first() {
-                window.set_foreground();
-                target.follow_focus_with_mouse(0)
-            } else {
-                target.set_cursor_pos_to_centre();
-                let mut enigo = Enigo::new();
-                enigo.mouse_click(MouseButton::Left)
-            }
+            self.focus_display_idx(to);
         }
     }
 
@@ -665,7 +1960,7 @@ impl Desktop {
         from: usize,
         direction: CycleDirection,
     ) {
-        let can_move = self.displays.len() > 1;
+        let can_move = self.displays.len() > 1 && window_idx < self.displays[from].windows.len();
 
         if can_move {
             let to = match direction {
@@ -685,209 +1980,2370 @@ impl Desktop {
                 }
             };
 
-            let window = {
-                let origin = self.displays[from].borrow_mut();
-                let window = origin.windows.remove(window_idx);
-                origin.calculate_layout();
-                origin.apply_layout(None);
-                window
-            };
-
-            let target = self.displays[to].borrow_mut();
-            target.windows.insert(0, window);
-            target.calculate_layout();
-            target.apply_layout(Option::from(0));
+            self.transfer_window(from, window_idx, to);
         }
     }
 
     pub fn move_window_to_display_number(&mut self, window_idx: usize, from: usize, to: usize) {
-        let can_move = to <= self.displays.len() && to > 0;
+        let can_move =
+            to <= self.displays.len() && to > 0 && window_idx < self.displays[from].windows.len();
 
         if can_move {
             let to = to - 1;
 
-            let window = {
-                let origin = self.displays[from].borrow_mut();
-                let window = origin.windows.remove(window_idx);
-                origin.calculate_layout();
-                origin.apply_layout(None);
-                window
-            };
-
-            let target = self.displays[to].borrow_mut();
-            target.windows.insert(0, window);
-            target.calculate_layout();
-            target.apply_layout(Option::from(0));
+            self.transfer_window(from, window_idx, to);
         }
     }
 
-    pub fn calculate_layouts(&mut self) {
-        for display in &mut self.displays {
-            display.calculate_layout()
-        }
+    /// The display index and window index of the managed window with `hwnd`
+    pub fn find_window(&self, hwnd: u64) -> Option<(usize, usize)> {
+        self.displays
+            .iter()
+            .enumerate()
+            .find_map(|(display_idx, display)| {
+                display
+                    .windows
+                    .iter()
+                    .position(|window| window.hwnd.0 as u64 == hwnd)
+                    .map(|window_idx| (display_idx, window_idx))
+            })
     }
 
-    pub fn apply_layouts(&mut self, new_focus: Option<usize>) {
-        for display in &mut self.displays {
-            display.apply_layout(new_focus)
-        }
+    /// Moves `window` over to the display that it is now on if it has been
+    /// moved there without going through yatta, for example by dragging it or
+    /// with Win+Shift+Arrow. Returns whether the window changed display.
+    pub fn track_window_display(&mut self, window: &Window) -> bool {
+        let (from, idx) = match self.find_window(window.hwnd.0 as u64) {
+            Some(found) => found,
+            None => return false,
+        };
+
+        let hmonitor = unsafe { MonitorFromWindow(window.hwnd, MONITOR_DEFAULTTONULL) };
+        let to = match self.displays.iter().position(|d| d.hmonitor == hmonitor) {
+            Some(to) if to != from => to,
+            _ => return false,
+        };
+
+        self.transfer_window(from, idx, to);
+
+        true
     }
-}
 
-impl Default for Desktop {
-    fn default() -> Self {
-        let mut desktop = Desktop {
-            displays: vec![],
-            paused:   false,
+    /// Moves the window at `idx` on display `from` over to display `to`, where
+    /// it goes in the first position that new windows can take
+    fn transfer_window(&mut self, from: usize, idx: usize, to: usize) {
+        let mut window = {
+            let origin = self.displays[from].borrow_mut();
+            let window = origin.windows[idx];
+            origin.unmanage_window(&window);
+            origin.calculate_layout();
+            origin.apply_layout(None);
+            window
         };
 
-        desktop.enumerate_display_monitors();
+        self.remember_placement(&window, to);
 
-        desktop.displays.sort_by(|x, y| {
-            let ordering = y.dimensions.x.cmp(&x.dimensions.x);
+        // Resize adjustments were relative to the neighbours on the old display
+        let target = self.displays[to].borrow_mut();
+        window.hmonitor = target.hmonitor;
+        window.resize = None;
 
-            if ordering == Ordering::Equal {
-                return y.dimensions.y.cmp(&x.dimensions.y);
-            }
+        let idx = target.first_free_idx();
+        target.windows.insert(idx, window);
+        target.calculate_layout();
+        target.apply_layout(Option::from(idx));
+    }
 
-            ordering
-        });
+    fn exe_multi_window(&self, exe: &str) -> MultiWindowBehaviour {
+        self.multi_window_exes
+            .get(exe)
+            .copied()
+            .unwrap_or(self.multi_window)
+    }
 
-        desktop.get_visible_windows();
-        for display in &mut desktop.displays {
-            display.get_foreground_window()
+    /// How `window` should be handled given the other windows of its process.
+    /// Only the first window of a process gets a tile of its own, unless its
+    /// exe is set to tile every window.
+    pub fn multi_window_behaviour(&self, window: &Window) -> MultiWindowBehaviour {
+        let behaviour = self.exe_multi_window(&window.exe_name());
+        if behaviour == MultiWindowBehaviour::Tile {
+            return behaviour;
         }
 
+        let (pid, _) = window.thread_process_id();
+        let already_tiled = self
+            .displays
+            .iter()
+            .flat_map(|d| d.windows.iter())
+            .any(|w| w != window && w.should_tile() && w.thread_process_id().0 == pid);
+
+        if already_tiled {
+            behaviour
+        } else {
+            MultiWindowBehaviour::Tile
+        }
+    }
+
+    fn remember_placement(&mut self, window: &Window, display_idx: usize) {
+        if let Some(history) = &mut self.placement_history {
+            history.record(window.exe_name(), display_idx + 1);
+        }
+    }
+
+    /// The index of the display that windows of the same exe as `window` were
+    /// last moved to, unless `window` is already being managed
+    pub fn remembered_display_idx(&self, window: &Window) -> Option<usize> {
+        let history = self.placement_history.as_ref()?;
+
+        if self.displays.iter().any(|d| d.windows.contains(window)) {
+            return None;
+        }
+
+        history
+            .display_number(&window.exe_name())
+            .and_then(|number| self.get_display_idx(number))
+    }
+
+    /// The config that would recreate the current state of the desktop, using
+    /// `base` for anything that can't be changed at runtime
+    pub fn effective_config(&self, base: &Config) -> Config {
+        let mut config = base.clone();
+
+        config.float_classes = self.rules.float_classes.clone();
+        config.float_exes = self.rules.float_exes.clone();
+        config.float_titles = self.rules.float_titles.clone();
+        config.float_windows = self.rules.float_windows.clone();
+        config.splash_exes = self.rules.splash_exes.clone();
+        config.name_change_on_launch_exes = self.rules.name_change_on_launch_exes.clone();
+        config.start_paused = self.paused;
+        config.focus_empty_display_with_click = self.focus_empty_display_with_click;
+        config.warp_cursor_on_display_focus = self.warp_cursor_on_display_focus;
+        config.active_display = self.active_display;
+        config.focus_wrap = self.focus_wrap;
+        config.dialog_behaviour = self.dialog_behaviour;
+        config.drag = self.drag;
+        config.multi_window = self.multi_window;
+        config.multi_window_exes = self.multi_window_exes.clone();
+        config.show_delay_ms = self.show_delay.as_millis() as u64;
+        config.immediate_show_exes = self.immediate_show_exes.clone();
+
+        config.displays = self.displays.iter().map(|d| d.overrides(base)).collect();
+
+        // Trailing displays without any overrides don't need to be written out
+        while config.displays.last() == Some(&DisplayConfig::default()) {
+            config.displays.pop();
+        }
+
+        config
+    }
+
+    /// The exes from the rules and the quirks table whose name changes are
+    /// handled as windows being shown
+    pub fn name_change_on_launch_exes(&self) -> Vec<String> {
+        let mut exes = self.rules.name_change_on_launch_exes.clone();
+        merge_rules(&mut exes, quirks::exes(|q| q.name_change_on_launch));
+        exes
+    }
+
+    /// Whether any of the rules float windows by their title, which can change
+    /// while they are managed
+    pub fn has_title_rules(&self) -> bool {
+        !self.rules.float_titles.is_empty()
+            || self
+                .rules
+                .float_windows
+                .iter()
+                .any(|rule| rule.title.is_some())
+    }
+
+    /// Works out again whether a managed window matches a float rule after its
+    /// title has changed, retiling its display if the answer is different
+    pub fn refresh_float_rule(&mut self, window: &Window) {
+        if !self.has_title_rules() {
+            return;
+        }
+
+        let float_rule = window.matches_float_rule(&self.rules, &self.float_windows);
+
+        for display in &mut self.displays {
+            if let Some(idx) = window.index(&display.windows) {
+                if display.windows[idx].float_rule != float_rule {
+                    display.windows[idx].float_rule = float_rule;
+                    display.calculate_layout();
+                    display.apply_layout(None);
+                }
+
+                return;
+            }
+        }
+    }
+
+    /// Adds any rules that aren't in place already, and works out again which
+    /// of the managed windows they float
+    pub fn add_rules(&mut self, rules: Rules) {
+        merge_rules(&mut self.rules.float_classes, rules.float_classes);
+        merge_rules(&mut self.rules.float_exes, rules.float_exes);
+        merge_rules(&mut self.rules.float_titles, rules.float_titles);
+        for rule in compile_float_windows(rules.float_windows) {
+            if !self.rules.float_windows.contains(rule.rule()) {
+                self.rules.float_windows.push(rule.rule().clone());
+                self.float_windows.push(rule);
+            }
+        }
+        merge_rules(&mut self.rules.splash_exes, rules.splash_exes);
+        merge_rules(
+            &mut self.rules.name_change_on_launch_exes,
+            rules.name_change_on_launch_exes,
+        );
+
+        let rules = &self.rules;
+        let float_windows = &self.float_windows;
+        for window in self.displays.iter_mut().flat_map(|d| d.windows.iter_mut()) {
+            window.float_rule = window.matches_float_rule(rules, float_windows);
+        }
+    }
+
+    /// Replaces the rules yatta is running with
+    pub fn set_rules(&mut self, rules: Rules) {
+        self.rules = Rules::default();
+        self.float_windows = vec![];
+        self.add_rules(rules);
+    }
+
+    /// Brings the desktop in line with `state`, only moving windows on the
+    /// displays whose settings actually change
+    pub fn apply_state(&mut self, state: DesiredState) {
+        if let Some(rules) = state.rules {
+            if rules != self.rules {
+                self.set_rules(rules);
+            }
+        }
+
+        let paused = self.paused;
+        for (display, desired) in self.displays.iter_mut().zip(state.displays) {
+            if display.apply_desired(desired) {
+                display.calculate_layout();
+                if !paused {
+                    display.apply_layout(None);
+                }
+            }
+        }
+
+        if let Some(paused) = state.paused {
+            self.set_paused(paused);
+        }
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        // The windows were let go of when suspended and have to be picked up again
+        if !paused && self.suspended.is_some() {
+            self.resume();
+            return;
+        }
+
+        if self.paused == paused {
+            return;
+        }
+
+        self.paused = paused;
+
+        // Apply any rules that were sent while we were paused
+        if !paused {
+            self.calculate_layouts();
+            self.apply_layouts(None);
+        }
+    }
+
+    /// Undoes everything yatta has done to windows that the user couldn't
+    /// easily undo themselves once it has exited: hidden or minimized Monocle
+    /// windows are shown again, corners are rounded again and zoomed windows
+    /// are put back in their tiles
+    pub fn restore_windows(&mut self) {
+        let paused = self.paused;
+
+        for display in &mut self.displays {
+            display.show_hidden_windows();
+
+            if display.square_corners {
+                for window in &display.windows {
+                    window.set_square_corners(false);
+                }
+            }
+
+            if display.zoomed.take().is_some() && !paused {
+                display.apply_layout(None);
+            }
+        }
+    }
+
+    /// Lets go of every window after restoring them, and leaves new windows
+    /// alone until resumed, for when the layout mustn't change while sharing
+    /// the screen and the like
+    pub fn suspend(&mut self) {
+        self.restore_windows();
+
+        let suspended = self.suspended.get_or_insert_with(Vec::new);
+        for display in &mut self.displays {
+            suspended.append(&mut display.windows);
+            display.layout_dimensions.clear();
+        }
+
+        self.paused = true;
+    }
+
+    /// Picks up the windows that are open now, which can be completely
+    /// different to the ones open when yatta was suspended, and tiles them.
+    /// Windows that were managed before the suspend keep their state and their
+    /// place. If yatta was only paused, it is just unpaused.
+    pub fn resume(&mut self) {
+        let suspended = match self.suspended.take() {
+            Some(suspended) => suspended,
+            None => {
+                self.set_paused(false);
+                return;
+            }
+        };
+
+        self.get_visible_windows();
+
+        for display in &mut self.displays {
+            let hmonitor = display.hmonitor;
+            let open = mem::take(&mut display.windows);
+
+            display.windows = suspended
+                .iter()
+                .filter(|window| {
+                    window.is_window()
+                        && window.is_visible()
+                        && unsafe { MonitorFromWindow(window.hwnd, MONITOR_DEFAULTTOPRIMARY) }
+                            == hmonitor
+                })
+                .map(|window| Window {
+                    hmonitor,
+                    ..*window
+                })
+                .collect();
+
+            for window in open {
+                if !display.windows.contains(&window) {
+                    display.windows.push(window);
+                }
+            }
+
+            display.get_foreground_window();
+        }
+
+        self.paused = false;
+        self.calculate_layouts();
+        self.apply_layouts(None);
+    }
+
+    /// Drops windows that have been destroyed or hidden, like apps that close
+    /// to the tray, from every display and re-tiles the displays they were
+    /// on so that they don't leave a gap behind. Windows hidden by yatta
+    /// are kept, and so is `except`, which is left to the event being handled
+    /// for it.
+    pub fn prune_windows(&mut self, except: &Window) {
+        for display in &mut self.displays {
+            let gone: Vec<Window> = display
+                .windows
+                .iter()
+                .filter(|x| *x != except && !(x.is_window() && (x.hidden || x.is_visible())))
+                .copied()
+                .collect();
+
+            if gone.is_empty() {
+                continue;
+            }
+
+            for window in &gone {
+                display.unmanage_window(window);
+            }
+
+            display.calculate_layout();
+            display.apply_layout(None);
+        }
+    }
+
+    /// Handles an event from the windows event listener, once the main loop has
+    /// made sure that the desktop isn't paused and pruned the other windows
+    /// that are gone
+    pub fn handle_windows_event(&mut self, mut ev: WindowsEvent) {
+        if ev.event_type == WindowsEventType::TitleChange {
+            self.refresh_float_rule(&ev.window);
+            return;
+        }
+
+        if matches!(
+            ev.event_type,
+            WindowsEventType::MoveResizeEnd | WindowsEventType::FocusChange
+        ) {
+            let changed_display = self.track_window_display(&ev.window);
+
+            // A window dropped on another display has already been tiled there
+            if changed_display && ev.event_type == WindowsEventType::MoveResizeEnd {
+                return;
+            }
+        }
+
+        let mut display_idx = self.get_active_display_idx();
+
+        // Windows that are closed or hidden are unmanaged from the display they
+        // are on, which might not be the active one
+        if matches!(
+            ev.event_type,
+            WindowsEventType::Hide | WindowsEventType::Destroy
+        ) {
+            if let Some((idx, _)) = self.find_window(ev.window.hwnd.0 as u64) {
+                display_idx = idx;
+            }
+        }
+
+        if ev.event_type == WindowsEventType::Show {
+            // Windows can be shown more than once, for example when they are restored or
+            // when a name change is treated as a Show, and should stay where they are
+            // unless their new title changes whether they float
+            if self.displays.iter().any(|d| d.windows.contains(&ev.window)) {
+                self.refresh_float_rule(&ev.window);
+                return;
+            }
+
+            if ev.window.is_splash(&self.rules) {
+                return;
+            }
+
+            ev.window.float_rule = ev
+                .window
+                .matches_float_rule(&self.rules, &self.float_windows);
+
+            // New windows go back to the display that their exe was last moved to
+            if let Some(idx) = self.remembered_display_idx(&ev.window) {
+                display_idx = idx;
+            }
+
+            // Some apps open helper windows next to their main window that shouldn't
+            // get a tile of their own
+            match self.multi_window_behaviour(&ev.window) {
+                MultiWindowBehaviour::Ignore => return,
+                MultiWindowBehaviour::Float => ev.window.tile = false,
+                MultiWindowBehaviour::Tile => {}
+            }
+        }
+
+        let drag = self.drag;
+        let display = self.displays[display_idx].borrow_mut();
+
+        info!(
+            "handling yatta channel message: {} ({})",
+            ev.event_type, ev.event_code
+        );
+
+        match ev.event_type {
+            WindowsEventType::MoveResizeStart => {
+                let idx = ev.window.index(&display.windows);
+
+                // Holding the modifier down when picking up a tiled window floats it
+                // straight away, so that the rest of the layout closes up behind it while
+                // it is moved around
+                if let Some(idx) = idx {
+                    if drag.title_bar_with_modifier == DragAction::Float
+                        && display.windows[idx].should_tile()
+                        && modifier_held(drag.modifier)
+                    {
+                        info!("floating window picked up with {:?} held", drag.modifier);
+                        display.float_in_place(idx);
+                        display.apply_layout(None);
+                        return;
+                    }
+                }
+
+                // Only raises the window, the rect is ignored with these flags
+                ev.window.set_pos(
+                    Rect::zero(),
+                    display.insert_after(Layer::Raised),
+                    Option::from(SWP_NOMOVE | SWP_NOSIZE),
+                );
+            }
+            WindowsEventType::MoveResizeEnd => {
+                // Windows that we don't manage on this display have no layout position to
+                // compare against
+                let idx = match ev.window.index(&display.windows) {
+                    Some(idx) => idx,
+                    None => return,
+                };
+
+                // Floating windows can go wherever they are dropped
+                if !display.windows[idx].should_tile() {
+                    return;
+                }
+
+                let old_position = match display.window_layout_rect(idx) {
+                    Some(rect) => rect,
+                    None => return,
+                };
+
+                let new_position = display.window_rect(&ev.window);
+
+                let mut resize = Rect::zero();
+                resize.x = new_position.x - old_position.x;
+                resize.y = new_position.y - old_position.y;
+                resize.width = new_position.width - old_position.width;
+                resize.height = new_position.height - old_position.height;
+
+                let is_move = resize.width == 0 && resize.height == 0;
+                let action = if !is_move {
+                    drag.border
+                } else if modifier_held(drag.modifier) {
+                    drag.title_bar_with_modifier
+                } else {
+                    drag.title_bar
+                };
+
+                match action {
+                    DragAction::Swap => {
+                        info!("handling move event");
+                        let cursor_pos: POINT = unsafe {
+                            let mut cursor_pos: POINT = mem::zeroed();
+                            GetCursorPos(&mut cursor_pos);
+                            cursor_pos
+                        };
+
+                        // Layout positions only count tiled windows, so the window in the
+                        // position under the cursor has to be looked up
+                        let target_window_idx = display
+                            .layout_dimensions
+                            .iter()
+                            .rposition(|rect| rect.contains_point((cursor_pos.x, cursor_pos.y)))
+                            .and_then(|position| display.tiled_window_idx(position))
+                            .filter(|i| display.windows[*i].hwnd != ev.window.hwnd);
+
+                        if let Some(new_idx) = target_window_idx {
+                            display.swap_windows(idx, new_idx);
+                        }
+                    }
+                    DragAction::Resize if !is_move => {
+                        info!("handling resize event");
+                        let mut ops = vec![];
+
+                        if resize.x != 0 {
+                            resize.x *= 2;
+                            let sizing = if resize.x > 0 {
+                                Sizing::Decrease
+                            } else {
+                                Sizing::Increase
+                            };
+
+                            ops.push((ResizeEdge::Left, sizing, resize.x.abs()))
+                        }
+
+                        if resize.y != 0 {
+                            resize.y *= 2;
+                            let sizing = if resize.y > 0 {
+                                Sizing::Decrease
+                            } else {
+                                Sizing::Increase
+                            };
+
+                            ops.push((ResizeEdge::Top, sizing, resize.y.abs()))
+                        }
+
+                        if resize.width != 0 && resize.x == 0 {
+                            resize.width *= 2;
+                            let sizing = if resize.width > 0 {
+                                Sizing::Increase
+                            } else {
+                                Sizing::Decrease
+                            };
+
+                            ops.push((ResizeEdge::Right, sizing, resize.width.abs()))
+                        }
+
+                        if resize.height != 0 && resize.y == 0 {
+                            resize.height *= 2;
+                            let sizing = if resize.height > 0 {
+                                Sizing::Increase
+                            } else {
+                                Sizing::Decrease
+                            };
+
+                            ops.push((ResizeEdge::Bottom, sizing, resize.height.abs()))
+                        }
+
+                        for (edge, sizing, step) in ops {
+                            display.resize_window(edge, sizing, Option::from(step));
+                        }
+
+                        display.calculate_layout();
+                    }
+                    DragAction::Float => {
+                        info!("floating dragged window");
+                        display.float_in_place(idx);
+                    }
+                    DragAction::Resize | DragAction::Snap => {}
+                }
+
+                display.apply_layout(None);
+            }
+            WindowsEventType::Show => {
+                let is_dialog = ev.window.is_dialog();
+                if is_dialog {
+                    match self.dialog_behaviour {
+                        DialogBehaviour::Ignore => return,
+                        DialogBehaviour::FloatCentered => ev.window.tile = false,
+                        DialogBehaviour::Tile => {}
+                    }
+                }
+
+                if display.manage_window(ev.window) {
+                    display.calculate_layout();
+                    display.apply_layout(None);
+
+                    // Tiled again once the pending layout delay is up
+                    if quirks::of(&ev.window.exe_name()).resnap {
+                        display.layout_pending = true;
+                    }
+
+                    if is_dialog && !ev.window.tile {
+                        ev.window.centre_in(
+                            display.get_dimensions(),
+                            display.insert_after(Layer::Floating),
+                        );
+                    }
+
+                    if let Some(title) = ev.window.title() {
+                        info!(
+                            "managing new window: {} - {} ({})",
+                            ev.window.exe_name(),
+                            &title,
+                            ev.window.hwnd.0
+                        );
+                    }
+                }
+            }
+            WindowsEventType::Hide | WindowsEventType::Destroy => {
+                // Windows hidden by the Monocle layout are still being managed
+                let hidden_by_us = display
+                    .windows
+                    .iter()
+                    .any(|w| w.hwnd == ev.window.hwnd && w.hidden);
+
+                if ev.event_type == WindowsEventType::Hide && hidden_by_us {
+                    return;
+                }
+
+                let focused = display.foreground_window.hwnd == ev.window.hwnd;
+                let new_focus = display.unmanage_window(&ev.window);
+                display.calculate_layout();
+                display.apply_layout(Option::from(new_focus));
+
+                // Otherwise Windows decides what to focus next
+                if focused {
+                    if let Some(window) = display.windows.get(new_focus) {
+                        window.set_foreground();
+                    }
+                }
+                if let Some(title) = ev.window.title() {
+                    info!("unmanaging window: {} ({})", &title, ev.window.hwnd.0);
+                }
+            }
+            WindowsEventType::FocusChange => {
+                // Only operate on windows we are tracking, some apps like explorer.exe send
+                // a focus change event before their show event
+                if display.focus_changed(ev.window) {
+                    if let Some(title) = ev.window.title() {
+                        info!(
+                            "focusing window: {} - {} ({})",
+                            ev.window.exe_name(),
+                            &title,
+                            ev.window.hwnd.0
+                        );
+                    }
+                }
+            }
+            // Handled before anything else above
+            WindowsEventType::TitleChange => {}
+        }
+    }
+
+    pub fn calculate_layouts(&mut self) {
+        for display in &mut self.displays {
+            display.calculate_layout()
+        }
+    }
+
+    pub fn apply_layouts(&mut self, new_focus: Option<usize>) {
+        for display in &mut self.displays {
+            display.apply_layout(new_focus)
+        }
+    }
+
+    /// Holds back moving any windows until `end_transaction`, so that a
+    /// series of commands only moves windows once
+    pub fn begin_transaction(&mut self) {
+        if self.transaction.is_none() {
+            self.transaction = Option::from(Instant::now());
+        }
+
+        for display in &mut self.displays {
+            display.in_transaction = true;
+        }
+    }
+
+    pub fn end_transaction(&mut self) {
+        self.transaction = None;
+
+        for display in &mut self.displays {
+            display.in_transaction = false;
+        }
+
+        self.apply_pending_layouts();
+    }
+
+    /// Holds back a show event for `show_delay`, unless its window belongs to
+    /// an exe on the immediate_show_exes list. Returns false if the event
+    /// should be handled straight away.
+    pub fn delay_show(&mut self, ev: &WindowsEvent) -> bool {
+        if self.show_delay == Duration::from_millis(0)
+            || self.immediate_show_exes.contains(&ev.window.exe_name())
+        {
+            return false;
+        }
+
+        self.delayed_shows
+            .push((Instant::now() + self.show_delay, ev.clone()));
+        true
+    }
+
+    /// When the next held back show event is due
+    pub fn next_show_due(&self) -> Option<Instant> {
+        self.delayed_shows.iter().map(|(due, _)| *due).min()
+    }
+
+    /// Takes the held back show events that are due by `now`, dropping those
+    /// whose windows have been closed or hidden in the meantime
+    pub fn due_shows(&mut self, now: Instant) -> Vec<WindowsEvent> {
+        let (due, waiting): (Vec<_>, Vec<_>) = self
+            .delayed_shows
+            .drain(..)
+            .partition(|(due, _)| *due <= now);
+        self.delayed_shows = waiting;
+
+        due.into_iter()
+            .map(|(_, ev)| ev)
+            .filter(|ev| {
+                let alive = ev.window.is_window() && ev.window.is_visible();
+                if !alive {
+                    info!(
+                        "ignoring window {} that was only shown briefly",
+                        ev.window.hwnd.0
+                    );
+                }
+
+                alive
+            })
+            .collect()
+    }
+
+    pub fn has_pending_layouts(&self) -> bool {
+        self.displays
+            .iter()
+            .any(|display| display.layout_pending && !display.in_transaction)
+    }
+
+    /// Moves the windows of the displays whose gaps have been adjusted, or
+    /// whose layout was held back by a click or a transaction, since the last
+    /// time this was called
+    pub fn apply_pending_layouts(&mut self) {
+        for display in &mut self.displays {
+            if display.layout_pending && !display.in_transaction {
+                display.layout_pending = false;
+                display.calculate_layout();
+                display.apply_layout(None);
+            }
+        }
+    }
+}
+
+impl Desktop {
+    /// Enumerates the connected displays and, if `initial_tile` is set in the
+    /// config, starts tiling the windows that are already open on them, timing
+    /// each step in `startup`. A paused desktop calculates its layouts but
+    /// doesn't move any windows until it is resumed.
+    pub fn new(config: &Config, startup: &mut StartupProfile) -> Self {
+        let mut desktop = Desktop::from_config(config);
+
+        if config.placement_history {
+            desktop.placement_history = dirs::home_dir()
+                .map(|home| PlacementHistory::load(home.join(PLACEMENT_HISTORY_FILE)));
+            startup.phase_done("placement history");
+        }
+
+        desktop.enumerate_display_monitors();
+        startup.phase_done("display enumeration");
+
+        if config.initial_tile {
+            desktop.get_visible_windows();
+            startup.phase_done("window enumeration");
+        }
+
+        desktop.configure_displays(config);
+        startup.phase_done("display configuration");
+
         desktop.calculate_layouts();
-        desktop.apply_layouts(None);
+        startup.phase_done("layout calculation");
+
+        if !desktop.paused {
+            desktop.apply_layouts(None);
+            startup.phase_done("layout application");
+        }
+
+        desktop
+    }
+
+    /// The desktop that `config` describes, before any displays or windows
+    /// have been looked up
+    fn from_config(config: &Config) -> Self {
+        Desktop {
+            displays:                       vec![],
+            paused:                         config.start_paused,
+            focus_empty_display_with_click: config.focus_empty_display_with_click,
+            warp_cursor_on_display_focus:   config.warp_cursor_on_display_focus,
+            active_display:                 config.active_display,
+            focus_wrap:                     config.focus_wrap,
+            placement_history:              None,
+            dialog_behaviour:               config.dialog_behaviour,
+            drag:                           config.drag,
+            multi_window:                   config.multi_window,
+            multi_window_exes:              config.multi_window_exes.clone(),
+            rules:                          Rules {
+                float_classes:              config.float_classes.clone(),
+                float_exes:                 config.float_exes.clone(),
+                float_titles:               config.float_titles.clone(),
+                float_windows:              config.float_windows.clone(),
+                splash_exes:                config.splash_exes.clone(),
+                name_change_on_launch_exes: config.name_change_on_launch_exes.clone(),
+            },
+            float_windows:                  compile_float_windows(config.float_windows.clone()),
+            profile:                        None,
+            transaction:                    None,
+            show_delay:                     Duration::from_millis(config.show_delay_ms),
+            immediate_show_exes:            config.immediate_show_exes.clone(),
+            delayed_shows:                  vec![],
+            suspended:                      None,
+        }
+    }
+}
+
+/// Finds the index of the rect closest to `origin` in `direction`, preferring
+/// rects that line up with it on the other axis. `origin` itself is never
+/// considered to be in any direction.
+fn nearest_in_direction(
+    origin: Rect,
+    direction: OperationDirection,
+    rects: &[Rect],
+) -> Option<usize> {
+    let (ox, oy) = origin.centre();
+
+    rects
+        .iter()
+        .enumerate()
+        .filter_map(|(i, rect)| {
+            let (x, y) = rect.centre();
+
+            // Whether the two rects share any of the vertical or horizontal axis
+            let same_row = overlaps(origin.y, origin.height, rect.y, rect.height);
+            let same_column = overlaps(origin.x, origin.width, rect.x, rect.width);
+
+            // Distance along the direction of travel and distance across it
+            let (along, across, aligned) = match direction {
+                OperationDirection::Left => (ox - x, y - oy, same_row),
+                OperationDirection::Right => (x - ox, y - oy, same_row),
+                OperationDirection::Up => (oy - y, x - ox, same_column),
+                OperationDirection::Down => (y - oy, x - ox, same_column),
+                OperationDirection::Previous | OperationDirection::Next => return None,
+            };
+
+            if along > 0 {
+                Option::from((!aligned, along, across.abs(), i))
+            } else {
+                None
+            }
+        })
+        .min()
+        .map(|(_, _, _, i)| i)
+}
+
+/// Moves `origin` onto the edge of `bounds` that is behind it when travelling
+/// in `direction`, so that a search in `direction` starts from that edge
+fn beyond_edge(origin: Rect, bounds: Rect, direction: OperationDirection) -> Rect {
+    match direction {
+        OperationDirection::Left => Rect {
+            x: bounds.x + bounds.width,
+            width: 0,
+            ..origin
+        },
+        OperationDirection::Right => Rect {
+            x: bounds.x,
+            width: 0,
+            ..origin
+        },
+        OperationDirection::Up => Rect {
+            y: bounds.y + bounds.height,
+            height: 0,
+            ..origin
+        },
+        OperationDirection::Down => Rect {
+            y: bounds.y,
+            height: 0,
+            ..origin
+        },
+        OperationDirection::Previous | OperationDirection::Next => origin,
+    }
+}
+
+fn merge_rules<T: PartialEq>(list: &mut Vec<T>, rules: Vec<T>) {
+    for rule in rules {
+        if !list.contains(&rule) {
+            list.push(rule);
+        }
+    }
+}
+
+fn overlaps(a_start: i32, a_len: i32, b_start: i32, b_len: i32) -> bool {
+    a_start < b_start + b_len && b_start < a_start + a_len
+}
+
+/// Every visible window that could be managed, whether it is or not
+pub fn open_windows() -> Vec<Window> {
+    let mut windows: Vec<Window> = vec![];
+
+    unsafe {
+        EnumWindows(
+            Some(enum_window),
+            LPARAM(&mut windows as *mut Vec<Window> as isize),
+        );
+    }
+
+    windows
+}
+
+/// The layout to use in place of `chosen` under the given layout rules
+fn ruled_layout(chosen: Layout, rules: &[&LayoutRule]) -> Layout {
+    let mut layout = rules.iter().find_map(|rule| rule.layout).unwrap_or(chosen);
+    let avoided = |layout: &Layout| rules.iter().any(|rule| rule.avoid.contains(layout));
+
+    let wanted = layout;
+    while avoided(&layout) {
+        layout.next();
+
+        // Every layout is avoided, so the rules can't all be satisfied
+        if layout == wanted {
+            break;
+        }
+    }
+
+    layout
+}
+
+/// Builds a new set of displays without touching the ones that are in use
+fn display_monitors() -> Vec<Display> {
+    let mut displays: Vec<Display> = vec![];
+
+    unsafe {
+        EnumDisplayMonitors(
+            HDC(0),
+            std::ptr::null_mut(),
+            Some(enum_display_monitor),
+            LPARAM(&mut displays as *mut Vec<Display> as isize),
+        );
+    }
+
+    displays.sort_by(|x, y| {
+        let ordering = y.dimensions.x.cmp(&x.dimensions.x);
+
+        if ordering == Ordering::Equal {
+            return y.dimensions.y.cmp(&x.dimensions.y);
+        }
+
+        ordering
+    });
+
+    displays
+}
+
+fn modifier_held(modifier: Modifier) -> bool {
+    // The most significant bit is set while the key is down
+    unsafe { GetAsyncKeyState(modifier.virtual_key()) < 0 }
+}
+
+fn mouse_button_held() -> bool {
+    // The most significant bit is set while the button is down
+    [VK_LBUTTON, VK_RBUTTON, VK_MBUTTON]
+        .iter()
+        .any(|button| unsafe { GetAsyncKeyState(*button) } < 0)
+}
+
+/// Synthesizes a left click at the current cursor position
+fn send_left_click() {
+    let input = |flags| INPUT {
+        r#type:    INPUT_MOUSE,
+        Anonymous: INPUT_0 {
+            mi: MOUSEINPUT {
+                dx:          0,
+                dy:          0,
+                mouseData:   0,
+                dwFlags:     flags,
+                time:        0,
+                dwExtraInfo: 0,
+            },
+        },
+    };
+
+    let mut inputs = [input(MOUSEEVENTF_LEFTDOWN), input(MOUSEEVENTF_LEFTUP)];
+
+    unsafe {
+        SendInput(
+            inputs.len() as u32,
+            inputs.as_mut_ptr(),
+            mem::size_of::<INPUT>() as i32,
+        );
+    }
+}
+
+extern "system" fn enum_window(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    let windows = unsafe { &mut *(lparam.0 as *mut Vec<Window>) };
+
+    let hmonitor = unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTOPRIMARY) };
+
+    let w = Window {
+        hwnd,
+        hmonitor,
+        ..Default::default()
+    };
+
+    if w.is_visible() && !w.is_minimized() && w.should_manage(None) {
+        windows.push(w)
+    }
+
+    true.into()
+}
+
+extern "system" fn enum_display_monitor(
+    monitor: HMONITOR,
+    _: HDC,
+    _: *mut RECT,
+    lparam: LPARAM,
+) -> BOOL {
+    let displays = unsafe { &mut *(lparam.0 as *mut Vec<Display>) };
+
+    let rect: Rect = unsafe {
+        let mut info: MONITORINFO = mem::zeroed();
+        info.cbSize = mem::size_of::<MONITORINFO>() as u32;
+
+        GetMonitorInfoW(monitor, &mut info as *mut MONITORINFO as *mut _);
+
+        info.rcWork.into()
+    };
+
+    displays.push(Display {
+        hmonitor: monitor,
+        dimensions: rect,
+        ..Default::default()
+    });
+
+    true.into()
+}
+
+/// Splits `area` into `count` columns (or rows), spreading `len` windows as
+/// evenly as possible between them and stacking any overflow within each one
+fn lanes(area: Rect, len: usize, count: usize, columns: bool) -> Vec<Rect> {
+    let count = if count == 0 || count > len {
+        len
+    } else {
+        count
+    };
+
+    let mut layouts: Vec<Rect> = vec![];
+    if count == 0 {
+        return layouts;
+    }
+
+    let (along, across) = if columns {
+        (area.width, area.height)
+    } else {
+        (area.height, area.width)
+    };
+
+    for lane in 0..count {
+        let (lane_offset, lane_size) = split(along, count, lane);
+
+        // Earlier lanes take the windows that don't divide evenly
+        let stacked = len / count + usize::from(lane < len % count);
+
+        for cell in 0..stacked {
+            let (cell_offset, cell_size) = split(across, stacked, cell);
+
+            layouts.push(if columns {
+                Rect {
+                    x:      area.x + lane_offset,
+                    y:      area.y + cell_offset,
+                    width:  lane_size,
+                    height: cell_size,
+                }
+            } else {
+                Rect {
+                    x:      area.x + cell_offset,
+                    y:      area.y + lane_offset,
+                    width:  cell_size,
+                    height: lane_size,
+                }
+            });
+        }
+    }
+
+    layouts
+}
+
+/// Centres the first window in `area` at `percent` of its width, splitting the
+/// rest into a column of rows on either side with the left taking any odd one
+/// out. A single window is left with blank space either side.
+fn centred(area: Rect, len: usize, percent: i32) -> Vec<Rect> {
+    if len == 0 {
+        return vec![];
+    }
+
+    let centre = centre_in(area, percent);
+    let mut layouts = vec![centre];
+
+    let others = len - 1;
+    let left = Rect {
+        width: centre.x - area.x,
+        ..area
+    };
+    let right = Rect {
+        x: centre.x + centre.width,
+        width: area.x + area.width - (centre.x + centre.width),
+        ..area
+    };
+
+    layouts.extend(lanes(left, others - others / 2, 1, true));
+    layouts.extend(lanes(right, others / 2, 1, true));
+
+    layouts
+}
+
+/// A rect `percent` of the width of `area` in the middle of it, at full height
+fn centre_in(area: Rect, percent: i32) -> Rect {
+    let width = area.width * percent.clamp(1, 100) / 100;
+
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        width,
+        ..area
+    }
+}
+
+/// Grows `rect` by `percent` around its centre, moving it back onto `bounds`
+/// rather than cutting it off where it would go past them
+fn zoom(rect: Rect, percent: i32, bounds: Rect) -> Rect {
+    let width = (rect.width * percent / 100).min(bounds.width);
+    let height = (rect.height * percent / 100).min(bounds.height);
+
+    let x = rect.x + (rect.width - width) / 2;
+    let y = rect.y + (rect.height - height) / 2;
+
+    Rect {
+        x: x.min(bounds.x + bounds.width - width).max(bounds.x),
+        y: y.min(bounds.y + bounds.height - height).max(bounds.y),
+        width,
+        height,
+    }
+}
+
+/// Offset and size of part `i` of `total` split into `parts`, with the last
+/// part taking up any rounding remainder so that the parts always add up
+fn split(total: i32, parts: usize, i: usize) -> (i32, i32) {
+    let size = total / parts as i32;
+    let offset = size * i as i32;
+
+    if i == parts - 1 {
+        (offset, total - offset)
+    } else {
+        (offset, size)
+    }
+}
+
+/// Insets the edges of `rect` that are shared with other tiles so that
+/// neighbouring tiles end up exactly `gap` apart, leaving the edges on the
+/// boundary of `area` alone
+fn inset_inner_edges(rect: Rect, area: Rect, gap: i32) -> Rect {
+    let before = gap / 2;
+    let after = gap - before;
+
+    let left = if rect.x > area.x { before } else { 0 };
+    let top = if rect.y > area.y { before } else { 0 };
+    let right = if rect.x + rect.width < area.x + area.width {
+        after
+    } else {
+        0
+    };
+    let bottom = if rect.y + rect.height < area.y + area.height {
+        after
+    } else {
+        0
+    };
+
+    Rect {
+        x:      rect.x + left,
+        y:      rect.y + top,
+        width:  rect.width - left - right,
+        height: rect.height - top - bottom,
+    }
+}
+
+fn bsp(
+    i: usize,
+    window_count: usize,
+    area: Rect,
+    vertical: usize,
+    resize_dimensions: Vec<Option<Rect>>,
+) -> Vec<Rect> {
+    let mut a = area;
+
+    let resized = if let Some(Some(r)) = resize_dimensions.get(i) {
+        a.x += r.x;
+        a.y += r.y;
+        a.width += r.width;
+        a.height += r.height;
+        a
+    } else {
+        area
+    };
+
+    if window_count == 0 {
+        vec![]
+    } else if window_count == 1 {
+        vec![resized]
+    } else if i % 2 == vertical {
+        let mut res = vec![Rect {
+            height: resized.height / 2,
+            ..resized
+        }];
+        res.append(&mut bsp(
+            i + 1,
+            window_count - 1,
+            Rect {
+                x:      area.x,
+                y:      area.y + resized.height / 2,
+                width:  area.width,
+                height: area.height - resized.height / 2,
+            },
+            vertical,
+            resize_dimensions,
+        ));
+        res
+    } else {
+        let mut res = vec![Rect {
+            width: resized.width / 2,
+            ..resized
+        }];
+        res.append(&mut bsp(
+            i + 1,
+            window_count - 1,
+            Rect {
+                x:      area.x + resized.width / 2,
+                y:      area.y,
+                width:  area.width - resized.width / 2,
+                height: area.height,
+            },
+            vertical,
+            resize_dimensions,
+        ));
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec as vec_of, option, prelude::*};
+
+    use super::*;
+    use yatta_core::{Query, SocketMessage, WindowRule, WireFormat};
+
+    use crate::{
+        config::{Length, Profile},
+        handle_socket_message,
+        handle_windows_event_message,
+        window::mock,
+        windows_event::{WinEventCode, WindowsEvent, WindowsEventType},
+    };
+
+    const AREA: Rect = Rect {
+        x:      0,
+        y:      0,
+        width:  1920,
+        height: 1080,
+    };
+
+    // None of these handles belong to a real window, so anything done to them
+    // through the Windows API fails without touching the desktop
+    fn window(hwnd: isize) -> Window {
+        Window {
+            hwnd: HWND(0x7fff_0000 + hwnd),
+            ..Default::default()
+        }
+    }
+
+    fn display() -> Display {
+        Display {
+            dimensions: AREA,
+            inner_gap: 0,
+            outer_gap: 0,
+            ..Default::default()
+        }
+    }
+
+    fn desktop(displays: Vec<Display>) -> Desktop {
+        Desktop {
+            displays,
+            ..Desktop::from_config(&Config::default())
+        }
+    }
+
+    fn event(event_type: WindowsEventType, hwnd: isize) -> WindowsEvent {
+        let event_code = match event_type {
+            WindowsEventType::Destroy => WinEventCode::ObjectDestroy,
+            WindowsEventType::FocusChange => WinEventCode::SystemForeground,
+            WindowsEventType::Hide => WinEventCode::ObjectHide,
+            WindowsEventType::Show => WinEventCode::ObjectShow,
+            WindowsEventType::MoveResizeStart => WinEventCode::SystemMoveSizeStart,
+            WindowsEventType::MoveResizeEnd => WinEventCode::SystemMoveSizeEnd,
+            WindowsEventType::TitleChange => WinEventCode::ObjectNameChange,
+        };
+
+        WindowsEvent {
+            event_type,
+            event_code,
+            window: window(hwnd),
+            title: None,
+        }
+    }
+
+    /// Feeds events to a display the same way as the main loop, windows that
+    /// have been closed with `mock::close` included. The display is kept in a
+    /// transaction while they are handled, so that the windows that don't exist
+    /// aren't moved around.
+    fn handle(display: &mut Display, events: Vec<WindowsEvent>) {
+        let (in_transaction, layout_pending) = (display.in_transaction, display.layout_pending);
+
+        let mut desktop = desktop(vec![display.clone()]);
+        desktop.displays[0].in_transaction = true;
+
+        for ev in events {
+            handle_windows_event_message(ev, &mut desktop);
+        }
+
+        *display = desktop.displays.remove(0);
+        display.in_transaction = in_transaction;
+        display.layout_pending = layout_pending;
+    }
+
+    /// A desktop with just `display` that never moves the cursor, clicks or
+    /// moves windows while it handles messages
+    fn quiet_desktop(mut display: Display) -> Desktop {
+        display.cursor_warp = CursorWarp::Never;
+
+        let mut desktop = desktop(vec![display]);
+        desktop.focus_empty_display_with_click = false;
+        desktop.warp_cursor_on_display_focus = false;
+        desktop.begin_transaction();
+        desktop
+    }
+
+    /// One of every message yattac can send, with every direction, layout and
+    /// edge they can be sent with, and `hwnd` for the ones that act on a
+    /// specific window. Stop is left out, as it exits.
+    fn every_message(hwnd: u64) -> Vec<SocketMessage> {
+        use OperationDirection::*;
+
+        let mut messages = vec![
+            SocketMessage::AdjustInnerGap(Sizing::Increase, 5),
+            SocketMessage::AdjustOuterGap(Sizing::Decrease, 5),
+            SocketMessage::MoveWindowToDisplayNumber(1),
+            SocketMessage::MoveWindowToDisplayNumber(2),
+            SocketMessage::FocusDisplayNumber(1),
+            SocketMessage::FocusDisplayNumber(2),
+            SocketMessage::Promote,
+            SocketMessage::BeginTransaction,
+            SocketMessage::EndTransaction,
+            SocketMessage::Retile(None),
+            SocketMessage::Retile(Option::from(2)),
+            SocketMessage::SetInnerGap(0, None),
+            SocketMessage::SetOuterGap(0, None),
+            SocketMessage::SetColumns(2, None),
+            SocketMessage::SetRows(2, None),
+            SocketMessage::ToggleFloat,
+            SocketMessage::FloatAll,
+            SocketMessage::TileAll,
+            SocketMessage::TogglePause,
+            SocketMessage::ToggleMonocle,
+            SocketMessage::ToggleZoomFocused,
+            SocketMessage::ToggleMaster,
+            SocketMessage::SetFloat(true),
+            SocketMessage::SetFloat(false),
+            SocketMessage::SetPause(true),
+            SocketMessage::SetMonocle(true),
+            SocketMessage::SetMonocle(false),
+            SocketMessage::Query(Query::State),
+            SocketMessage::FloatClass("Chrome_WidgetWin_1".to_string()),
+            SocketMessage::FloatExe("code.exe".to_string()),
+            SocketMessage::FloatTitle("open".to_string()),
+            SocketMessage::FocusWindowByHwnd(hwnd),
+            SocketMessage::FloatWindowByHwnd(hwnd, true),
+            SocketMessage::FloatWindowByHwnd(hwnd, false),
+            SocketMessage::MoveHwndToDisplayNumber(hwnd, 1),
+            SocketMessage::MoveHwndToDisplayNumber(hwnd, 2),
+            SocketMessage::ImportRules(Rules::default()),
+            SocketMessage::ApplyState(DesiredState::default()),
+            SocketMessage::Suspend,
+            SocketMessage::Resume,
+            SocketMessage::Handshake(WireFormat::MessagePack),
+            SocketMessage::Authenticate("token".to_string()),
+            SocketMessage::Subscribe,
+        ];
+
+        for direction in [Left, Right, Up, Down, Previous, Next].iter().copied() {
+            messages.push(SocketMessage::FocusWindow(direction));
+            messages.push(SocketMessage::MoveWindow(direction));
+            messages.push(SocketMessage::SwapWithFloat(direction));
+        }
+
+        for direction in [CycleDirection::Previous, CycleDirection::Next]
+            .iter()
+            .copied()
+        {
+            messages.push(SocketMessage::FocusFloat(direction));
+            messages.push(SocketMessage::MoveWindowToDisplay(direction));
+            messages.push(SocketMessage::FocusDisplay(direction));
+            messages.push(SocketMessage::CycleLayout(direction, None));
+            messages.push(SocketMessage::CycleTileOccupants(direction));
+        }
+
+        for layout in [
+            Layout::BSPV,
+            Layout::BSPH,
+            Layout::Columns,
+            Layout::Rows,
+            Layout::Centred,
+            Layout::Monocle,
+        ]
+        .iter()
+        .copied()
+        {
+            messages.push(SocketMessage::Layout(layout, None));
+        }
+
+        for edge in [
+            ResizeEdge::Left,
+            ResizeEdge::Top,
+            ResizeEdge::Right,
+            ResizeEdge::Bottom,
+        ]
+        .iter()
+        .copied()
+        {
+            messages.push(SocketMessage::ResizeWindow(edge, Sizing::Increase));
+            messages.push(SocketMessage::ResizeWindow(edge, Sizing::Decrease));
+        }
+
+        messages
+    }
+
+    fn order(display: &Display) -> Vec<isize> {
+        display
+            .windows
+            .iter()
+            .map(|w| w.hwnd.0 - 0x7fff_0000)
+            .collect()
+    }
+
+    #[test]
+    fn new_windows_go_after_the_foreground_window() {
+        use WindowsEventType::*;
+
+        let mut display = display();
+        handle(
+            &mut display,
+            vec![
+                event(Show, 1),
+                event(FocusChange, 1),
+                event(Show, 2),
+                event(FocusChange, 2),
+                event(Show, 3),
+                event(FocusChange, 1),
+                event(Show, 4),
+            ],
+        );
+
+        assert_eq!(order(&display), vec![1, 4, 2, 3]);
+        assert_eq!(display.layout_dimensions.len(), 4);
+    }
+
+    #[test]
+    fn windows_11_compat_overlaps_borders_without_a_gap() {
+        use WindowsEventType::*;
+
+        let mut display = display();
+        display.windows_11_compat = true;
+        handle(&mut display, vec![event(Show, 1), event(Show, 2)]);
+
+        let (left, right) = (display.layout_dimensions[0], display.layout_dimensions[1]);
+        assert_eq!(left.x + left.width, right.x + WINDOWS_11_BORDER);
+
+        display.inner_gap = 10;
+        display.calculate_layout();
+
+        let (left, right) = (display.layout_dimensions[0], display.layout_dimensions[1]);
+        assert_eq!(left.x + left.width + 10, right.x);
+    }
+
+    #[test]
+    fn repeated_show_events_are_ignored() {
+        use WindowsEventType::*;
+
+        let mut display = display();
+        handle(
+            &mut display,
+            vec![
+                event(Show, 1),
+                event(Show, 1),
+                event(Show, 2),
+                event(Show, 2),
+            ],
+        );
+
+        assert_eq!(order(&display), vec![1, 2]);
+    }
+
+    #[test]
+    fn closed_window_position_goes_to_the_previous_window() {
+        use WindowsEventType::*;
+
+        let mut display = display();
+        display.space_inheritance = SpaceInheritance::Previous;
+        display.windows = (1..=4).map(window).collect();
+
+        handle(&mut display, vec![event(Destroy, 3)]);
+        assert_eq!(order(&display), vec![1, 4, 2]);
+
+        // The last window's space already goes to the one before it
+        handle(&mut display, vec![event(Destroy, 2)]);
+        assert_eq!(order(&display), vec![1, 4]);
+    }
+
+    #[test]
+    fn windows_that_are_already_gone_are_unmanaged_like_any_other() {
+        use WindowsEventType::*;
+
+        let mut display = display();
+        display.space_inheritance = SpaceInheritance::Previous;
+        display.windows = (1..=4).map(window).collect();
+        display.zoomed = Option::from(window(3).hwnd);
+        handle(&mut display, vec![event(FocusChange, 3)]);
+
+        // By the time the event arrives the window no longer exists
+        mock::close(window(3).hwnd);
+        handle(&mut display, vec![event(Destroy, 3)]);
+
+        assert_eq!(order(&display), vec![1, 4, 2]);
+        assert_eq!(display.zoomed, None);
+        assert!(!display.focus_history.contains(&window(3).hwnd));
+    }
+
+    #[test]
+    fn layout_positions_skip_floating_windows() {
+        let mut display = display();
+        display.windows = (1..=3).map(window).collect();
+        display.windows[0].tile = false;
+        display.calculate_layout();
+
+        assert_eq!(display.layout_dimensions.len(), 2);
+        assert_eq!(display.tiled_position(0), None);
+        assert_eq!(display.tiled_position(2), Some(1));
+        assert_eq!(display.tiled_window_idx(1), Some(2));
+        assert_eq!(display.window_layout_rect(0), None);
+        assert_eq!(
+            display.window_layout_rect(2),
+            Some(display.layout_dimensions[1])
+        );
+    }
+
+    #[test]
+    fn tile_at_skips_floating_windows_before_it() {
+        let mut display = display();
+        display.windows = (1..=3).map(window).collect();
+        display.windows[0].tile = false;
+        display.calculate_layout();
+
+        let right = display.layout_dimensions[1];
+        let idx = display.tile_at((right.x + right.width / 2, right.y + right.height / 2));
+
+        // A new window inserted here takes over the tile of window 3
+        assert_eq!(idx, Some(2));
+        display.windows.insert(idx.unwrap(), window(4));
+        assert_eq!(display.tiled_position(2), Some(1));
+    }
+
+    #[test]
+    fn focus_change_for_unmanaged_window_is_ignored() {
+        use WindowsEventType::*;
+
+        let mut display = display();
+        handle(
+            &mut display,
+            vec![event(Show, 1), event(FocusChange, 1), event(FocusChange, 9)],
+        );
+
+        assert_eq!(display.foreground_window, window(1));
+    }
+
+    #[test]
+    fn focus_change_leaves_the_layout_alone() {
+        use WindowsEventType::*;
+
+        let mut display = display();
+        handle(
+            &mut display,
+            vec![event(Show, 1), event(Show, 2), event(Show, 3)],
+        );
+        let layout = display.layout_dimensions.clone();
 
-        desktop
+        assert!(display.focus_changed(window(2)));
+        assert_eq!(display.foreground_window.hwnd, window(2).hwnd);
+        assert_eq!(order(&display), vec![1, 3, 2]);
+        assert_eq!(display.layout_dimensions, layout);
     }
-}
 
-extern "system" fn enum_window(hwnd: HWND, lparam: LPARAM) -> BOOL {
-    let windows = unsafe { &mut *(lparam.0 as *mut Vec<Window>) };
+    #[test]
+    fn hiding_a_window_retiles_the_rest() {
+        use WindowsEventType::*;
 
-    let hmonitor = unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTOPRIMARY) };
+        let mut display = display();
+        handle(
+            &mut display,
+            vec![event(Show, 1), event(Show, 2), event(Show, 3)],
+        );
+        assert_eq!(display.layout_dimensions.len(), 3);
 
-    let w = Window {
-        hwnd,
-        hmonitor,
-        tile: true,
-        resize: None,
-    };
+        handle(&mut display, vec![event(Hide, 2), event(Destroy, 9)]);
 
-    if w.is_visible() && !w.is_minimized() && w.should_manage(None) {
-        windows.push(w)
+        assert_eq!(order(&display), vec![1, 3]);
+        assert_eq!(
+            display.layout_dimensions,
+            vec![
+                Rect {
+                    x:      0,
+                    y:      0,
+                    width:  960,
+                    height: 1080,
+                },
+                Rect {
+                    x:      960,
+                    y:      0,
+                    width:  960,
+                    height: 1080,
+                },
+            ]
+        );
     }
 
-    true.into()
-}
+    #[test]
+    fn windows_closed_without_an_event_are_pruned_on_the_next_one() {
+        use WindowsEventType::*;
 
-extern "system" fn enum_display_monitor(
-    monitor: HMONITOR,
-    _: HDC,
-    _: *mut RECT,
-    lparam: LPARAM,
-) -> BOOL {
-    let displays = unsafe { &mut *(lparam.0 as *mut Vec<Display>) };
+        let mut display = display();
+        handle(
+            &mut display,
+            vec![event(Show, 1), event(Show, 2), event(Show, 3)],
+        );
 
-    let rect: Rect = unsafe {
-        let mut info: MONITORINFO = mem::zeroed();
-        info.cbSize = mem::size_of::<MONITORINFO>() as u32;
+        mock::close(window(2).hwnd);
+        handle(&mut display, vec![event(FocusChange, 3)]);
 
-        GetMonitorInfoW(monitor, &mut info as *mut MONITORINFO as *mut _);
+        assert_eq!(order(&display), vec![1, 3]);
+        assert_eq!(display.layout_dimensions.len(), 2);
+    }
 
-        info.rcWork.into()
-    };
+    #[test]
+    fn floating_windows_stay_above_tiles() {
+        let mut display = display();
+        assert_eq!(display.insert_after(Layer::Tiled), Some(HWND_BOTTOM));
+        assert_eq!(display.insert_after(Layer::Floating), Some(HWND_TOP));
 
-    let padding = PADDING.lock().unwrap();
+        display.z_order = ZOrder::Preserve;
+        assert_eq!(display.insert_after(Layer::Tiled), None);
+        assert_eq!(display.insert_after(Layer::Floating), None);
+        assert_eq!(display.insert_after(Layer::Raised), Some(HWND_TOP));
+    }
 
-    displays.push(Display {
-        dimensions:        rect,
-        foreground_window: Window::default(),
-        gaps:              5,
-        padding:           *padding,
-        resize_step:       50,
-        hmonitor:          monitor,
-        layout:            Layout::BSPV,
-        layout_dimensions: vec![],
-        windows:           vec![],
-    });
+    #[test]
+    fn the_first_matching_profile_is_used() {
+        let profile = |name: &str, display_count, resolutions: &[&str]| Profile {
+            name: name.to_string(),
+            display_count,
+            resolutions: resolutions.iter().map(|r| r.to_string()).collect(),
+            layout: Option::from(Layout::Columns),
+            ..Default::default()
+        };
 
-    true.into()
-}
+        let config = Config {
+            profiles: vec![
+                profile("docked", None, &["3440x1440", "1920x1080"]),
+                profile("two", Option::from(2), &[]),
+                profile("any", None, &[]),
+            ],
+            ..Default::default()
+        };
 
-fn bsp(
-    i: usize,
-    window_count: usize,
-    area: Rect,
-    vertical: usize,
-    gaps: i32,
-    resize_dimensions: Vec<Option<Rect>>,
-) -> Vec<Rect> {
-    let mut a = area;
+        let resolutions = |r: &[&str]| r.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        let name = |r: &[&str]| config.profile(&resolutions(r)).map(|p| p.name.clone());
 
-    let resized = if let Some(Some(r)) = resize_dimensions.get(i) {
-        a.x += r.x;
-        a.y += r.y;
-        a.width += r.width;
-        a.height += r.height;
-        a
-    } else {
-        area
-    };
+        assert_eq!(
+            name(&["3440x1440", "1920x1080"]),
+            Some("docked".to_string())
+        );
+        assert_eq!(name(&["1920x1080", "3440x1440"]), Some("two".to_string()));
+        assert_eq!(name(&["1920x1080"]), Some("any".to_string()));
 
-    if window_count == 0 {
-        vec![]
-    } else if window_count == 1 {
-        vec![Rect {
-            x:      resized.x + gaps,
-            y:      resized.y + gaps,
-            width:  resized.width - gaps * 2,
-            height: resized.height - gaps * 2,
-        }]
-    } else if i % 2 == vertical {
-        let mut res = vec![Rect {
-            x:      resized.x + gaps,
-            y:      resized.y + gaps,
-            width:  resized.width - gaps * 2,
-            height: resized.height / 2 - gaps * 2,
-        }];
-        res.append(&mut bsp(
-            i + 1,
-            window_count - 1,
-            Rect {
-                x:      area.x,
-                y:      area.y + resized.height / 2,
-                width:  area.width,
-                height: area.height - resized.height / 2,
-            },
-            vertical,
-            gaps,
-            resize_dimensions,
-        ));
-        res
-    } else {
-        let mut res = vec![Rect {
-            x:      resized.x + gaps,
-            y:      resized.y + gaps,
-            width:  resized.width / 2 - gaps * 2,
-            height: resized.height - gaps * 2,
-        }];
-        res.append(&mut bsp(
-            i + 1,
-            window_count - 1,
+        let profiled = config.with_profile(&config.profiles[0]);
+        assert_eq!(profiled.layout, Layout::Columns);
+        assert_eq!(profiled.inner_gap, config.inner_gap);
+    }
+
+    #[test]
+    fn cycling_tile_occupants_goes_back_through_previous_windows() {
+        let mut display = display();
+        display.cursor_warp = CursorWarp::Never;
+        display.in_transaction = true;
+        display.windows = (1..=3).map(window).collect();
+
+        // Applying a layout records the occupants, which a transaction holds back
+        display.record_occupants();
+        display.swap_windows(0, 1);
+        display.record_occupants();
+        display.swap_windows(0, 2);
+        display.record_occupants();
+        assert_eq!(order(&display), vec![3, 1, 2]);
+
+        display.foreground_window = window(3);
+        display.cycle_tile_occupants(CycleDirection::Next);
+        assert_eq!(order(&display), vec![2, 1, 3]);
+
+        display.foreground_window = window(2);
+        display.cycle_tile_occupants(CycleDirection::Next);
+        assert_eq!(order(&display), vec![1, 2, 3]);
+
+        display.foreground_window = window(1);
+        display.cycle_tile_occupants(CycleDirection::Previous);
+        assert_eq!(order(&display), vec![2, 1, 3]);
+    }
+
+    #[test]
+    fn reconnecting_displays_keeps_runtime_settings_unless_the_profile_changes() {
+        let config = Config {
+            profiles: vec![Profile {
+                name: "two".to_string(),
+                display_count: Option::from(2),
+                layout: Option::from(Layout::Columns),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let mut desktop = desktop(vec![display()]);
+        desktop.displays[0].windows.push(window(1));
+        desktop.displays[0].layout = Layout::Rows;
+        desktop.displays[0].inner_gap = 3;
+
+        desktop.reconnect(vec![display()], &config);
+        assert_eq!(desktop.profile, None);
+        assert_eq!(desktop.displays[0].layout, Layout::Rows);
+        assert_eq!(desktop.displays[0].inner_gap, 3);
+        assert_eq!(order(&desktop.displays[0]), vec![1]);
+
+        let mut second = display();
+        second.hmonitor = HMONITOR(1);
+        desktop.reconnect(vec![display(), second], &config);
+        assert_eq!(desktop.profile.as_deref(), Option::from("two"));
+        assert!(desktop
+            .displays
+            .iter()
+            .all(|display| display.layout == Layout::Columns));
+    }
+
+    #[test]
+    fn resuming_when_only_paused_keeps_the_windows() {
+        let mut desktop = desktop(vec![display()]);
+        for hwnd in 1..=3 {
+            desktop.displays[0].windows.push(window(hwnd));
+        }
+        desktop.paused = true;
+
+        desktop.resume();
+
+        assert!(!desktop.paused);
+        assert_eq!(order(&desktop.displays[0]), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn unpausing_after_a_suspend_resumes() {
+        let mut display = display();
+        display.windows = (1..=3).map(window).collect();
+        let mut desktop = quiet_desktop(display);
+
+        handle_socket_message(SocketMessage::SetPause(true), &mut desktop);
+        handle_socket_message(SocketMessage::Suspend, &mut desktop);
+        assert!(desktop.suspended.is_some());
+
+        handle_socket_message(SocketMessage::TogglePause, &mut desktop);
+        assert!(!desktop.paused);
+        assert!(desktop.suspended.is_none());
+    }
+
+    #[test]
+    fn every_message_leaves_an_empty_display_empty() {
+        // Focusing an empty display hands focus to the real desktop window
+        let messages = every_message(window(1).hwnd.0 as u64)
+            .into_iter()
+            .filter(|msg| {
+                !matches!(
+                    msg,
+                    SocketMessage::FocusDisplayNumber(_) | SocketMessage::FocusDisplay(_)
+                )
+            });
+
+        for msg in messages {
+            let mut desktop = quiet_desktop(display());
+            handle_socket_message(msg.clone(), &mut desktop);
+
+            assert!(desktop.displays[0].windows.is_empty(), "{:?}", msg);
+        }
+    }
+
+    #[test]
+    fn every_message_keeps_the_only_window_of_a_display() {
+        use WindowsEventType::*;
+
+        let mut display = display();
+        handle(&mut display, vec![event(Show, 1), event(FocusChange, 1)]);
+
+        for msg in every_message(window(1).hwnd.0 as u64) {
+            let mut desktop = quiet_desktop(display.clone());
+            handle_socket_message(msg.clone(), &mut desktop);
+
+            // Suspending lets go of the window until yatta is resumed
+            let windows = match desktop.suspended {
+                Some(suspended) => suspended,
+                None => desktop.displays.remove(0).windows,
+            };
+
+            assert_eq!(windows, vec![window(1)], "{:?}", msg);
+        }
+    }
+
+    #[test]
+    fn each_display_goes_back_to_its_own_layout_from_monocle() {
+        let mut second = display();
+        second.hmonitor = HMONITOR(1);
+
+        let mut desktop = desktop(vec![display(), second]);
+        desktop.begin_transaction();
+        for (display, layout) in desktop
+            .displays
+            .iter_mut()
+            .zip([Layout::Columns, Layout::Rows].iter().copied())
+        {
+            display.windows = (1..=2).map(window).collect();
+            display.set_layout(layout);
+            display.set_monocle(true);
+            assert_eq!(display.layout, Layout::Monocle);
+        }
+
+        desktop.displays[1].set_monocle(false);
+        assert_eq!(desktop.displays[0].layout, Layout::Monocle);
+        assert_eq!(desktop.displays[1].layout, Layout::Rows);
+
+        desktop.displays[0].set_monocle(false);
+        assert_eq!(desktop.displays[0].layout, Layout::Columns);
+        assert_eq!(desktop.displays[1].layout, Layout::Rows);
+    }
+
+    #[test]
+    fn window_rules_need_every_property_they_have() {
+        let rule = FloatWindowRule::new(WindowRule {
+            exe: Option::from("Code.exe".to_string()),
+            title: Option::from("^(open|öffnen)".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let class = Option::from("Chrome_WidgetWin_1");
+        let exe = Option::from("code.exe");
+        assert!(rule.matches(class, exe, Option::from("Öffnen - Ordner")));
+        assert!(rule.matches(class, exe, Option::from("OPEN folder")));
+        assert!(!rule.matches(class, exe, Option::from("main.rs")));
+        assert!(!rule.matches(class, None, Option::from("Open folder")));
+
+        let empty = FloatWindowRule::new(WindowRule::default()).unwrap();
+        assert!(!empty.matches(class, exe, None));
+    }
+
+    #[test]
+    fn invalid_window_rules_are_rejected_when_added() {
+        let mut desktop = desktop(vec![display()]);
+        let valid = WindowRule {
+            title: Option::from("^open".to_string()),
+            ..Default::default()
+        };
+        let invalid = WindowRule {
+            title: Option::from("(open".to_string()),
+            ..Default::default()
+        };
+
+        assert!(FloatWindowRule::new(invalid.clone()).is_err());
+
+        desktop.add_rules(Rules {
+            float_windows: vec![invalid, valid.clone()],
+            ..Default::default()
+        });
+
+        assert_eq!(desktop.rules.float_windows, vec![valid]);
+        assert_eq!(desktop.float_windows.len(), 1);
+    }
+
+    #[test]
+    fn unmanaging_focuses_the_previous_window() {
+        let mut display = display();
+        for hwnd in 1..=3 {
+            display.windows.push(window(hwnd));
+        }
+
+        assert_eq!(display.unmanage_window(&window(3)), 1);
+        assert_eq!(display.unmanage_window(&window(1)), 0);
+        assert_eq!(display.unmanage_window(&window(9)), 0);
+        assert_eq!(order(&display), vec![2]);
+    }
+
+    #[test]
+    fn unmanaging_can_focus_the_most_recent_window() {
+        use WindowsEventType::*;
+
+        let mut display = display();
+        display.focus_after_close = FocusAfterClose::Recent;
+        display.windows = (1..=4).map(window).collect();
+        handle(
+            &mut display,
+            vec![
+                event(FocusChange, 3),
+                event(FocusChange, 1),
+                event(FocusChange, 4),
+            ],
+        );
+
+        assert_eq!(display.unmanage_window(&window(4)), 0);
+        assert_eq!(display.unmanage_window(&window(1)), 1);
+    }
+
+    #[test]
+    fn new_window_takes_over_resize_of_the_window_it_displaces() {
+        use WindowsEventType::*;
+
+        let resize = Rect {
+            x:      0,
+            y:      0,
+            width:  100,
+            height: 0,
+        };
+
+        let mut display = display();
+        handle(&mut display, vec![event(Show, 1), event(Show, 2)]);
+        display.windows[1].resize = Option::from(resize);
+
+        handle(&mut display, vec![event(FocusChange, 1), event(Show, 3)]);
+
+        assert_eq!(order(&display), vec![1, 3, 2]);
+        assert_eq!(display.windows[1].resize, Option::from(resize));
+        assert_eq!(display.windows[2].resize, None);
+    }
+
+    #[test]
+    fn swapping_windows_leaves_resize_adjustments_in_place() {
+        let resize = Rect {
+            x:      0,
+            y:      0,
+            width:  100,
+            height: 0,
+        };
+
+        let mut display = display();
+        for hwnd in 1..=3 {
+            display.windows.push(window(hwnd));
+        }
+        display.windows[1].resize = Option::from(resize);
+
+        display.swap_windows(1, 2);
+
+        assert_eq!(order(&display), vec![1, 3, 2]);
+        assert_eq!(display.windows[1].resize, Option::from(resize));
+        assert_eq!(display.windows[2].resize, None);
+    }
+
+    #[test]
+    fn floating_windows_are_not_tiled() {
+        use WindowsEventType::*;
+
+        let mut display = display();
+        handle(&mut display, vec![event(Show, 1), event(Show, 2)]);
+        display.windows[0].tile = false;
+        display.calculate_layout();
+
+        assert_eq!(display.layout_dimensions, vec![AREA]);
+    }
+
+    #[test]
+    fn the_master_window_keeps_its_position() {
+        use WindowsEventType::*;
+
+        let mut display = display();
+        handle(
+            &mut display,
+            vec![event(Show, 1), event(Show, 2), event(Show, 3)],
+        );
+        display.master = Option::from(window(1).hwnd);
+        let before = order(&display);
+
+        display.swap_windows(0, 2);
+        assert_eq!(order(&display), before);
+        assert_eq!(display.first_free_idx(), 1);
+
+        handle(&mut display, vec![event(Destroy, 1)]);
+        assert_eq!(display.master, None);
+        assert_eq!(display.first_free_idx(), 0);
+    }
+
+    #[test]
+    fn a_master_window_moved_to_another_display_is_no_longer_its_master() {
+        let mut first = display();
+        first.windows = (1..=3).map(window).collect();
+        first.master = Option::from(window(1).hwnd);
+        first.focus_history = vec![window(1).hwnd];
+        first.record_occupants();
+
+        let second = Display {
+            hmonitor: HMONITOR(1),
+            ..display()
+        };
+
+        let mut desktop = desktop(vec![first, second]);
+        for display in &mut desktop.displays {
+            display.cursor_warp = CursorWarp::Never;
+        }
+        desktop.begin_transaction();
+
+        desktop.move_window_to_display_number(0, 0, 2);
+
+        let first = &desktop.displays[0];
+        assert_eq!(order(first), vec![2, 3]);
+        assert_eq!(first.master, None);
+        assert!(first.focus_history.is_empty());
+        assert!(first.occupants.iter().all(|o| !o.contains(&window(1).hwnd)));
+        assert_eq!(order(&desktop.displays[1]), vec![1]);
+    }
+
+    #[test]
+    fn tiling_all_restores_the_windows_floated_by_float_all() {
+        use WindowsEventType::*;
+
+        let mut display = display();
+        handle(
+            &mut display,
+            vec![event(Show, 1), event(Show, 2), event(Show, 3)],
+        );
+        display.windows[1].tile = false;
+
+        display.float_all();
+        assert!(display.windows.iter().all(|w| !w.tile));
+
+        display.tile_all();
+        let tiled: Vec<bool> = display.windows.iter().map(|w| w.tile).collect();
+        assert_eq!(tiled, vec![true, false, true]);
+    }
+
+    #[test]
+    fn floating_windows_use_the_configured_float_size() {
+        let mut display = display();
+        display.float_size = FloatSize {
+            width:  Length::Pixels(800),
+            height: Length::Percent(50),
+        };
+
+        assert_eq!(
+            display.floating_rect(&window(1)),
             Rect {
-                x:      area.x + resized.width / 2,
-                y:      area.y,
-                width:  area.width - resized.width / 2,
-                height: area.height,
-            },
-            vertical,
-            gaps,
-            resize_dimensions,
-        ));
-        res
+                x:      560,
+                y:      270,
+                width:  800,
+                height: 540,
+            }
+        );
+    }
+
+    #[test]
+    fn floating_and_zoomed_windows_are_restacked_above_tiles() {
+        use WindowsEventType::*;
+
+        let mut display = display();
+        handle(
+            &mut display,
+            vec![event(Show, 1), event(Show, 2), event(Show, 3)],
+        );
+        let zoomed = display.windows[0].hwnd;
+        display.zoomed = Option::from(zoomed);
+        display.windows[2].tile = false;
+        let floating = display.windows[2].hwnd;
+
+        let order: Vec<HWND> = display.restack_order().iter().map(|w| w.hwnd).collect();
+        assert_eq!(order, vec![floating, zoomed]);
+        assert_eq!(display.layer(&display.windows[1]), Layer::Tiled);
+
+        display.z_order = ZOrder::Preserve;
+        assert!(display.restack_order().is_empty());
+    }
+
+    #[test]
+    fn cursor_warp_points_stay_on_the_display() {
+        let half_off = Rect {
+            x:      1440,
+            y:      0,
+            width:  960,
+            height: 1080,
+        };
+
+        let points = half_off.warp_points(AREA);
+        assert_eq!(points[0], (1680, 540));
+        assert!(points.iter().all(|point| AREA.contains_point(*point)));
+    }
+
+    #[test]
+    fn layouts_are_held_back_during_a_transaction() {
+        use WindowsEventType::*;
+
+        let mut display = display();
+        display.in_transaction = true;
+        handle(&mut display, vec![event(Show, 1)]);
+        display.apply_layout(None);
+
+        assert!(display.layout_pending);
+    }
+
+    #[test]
+    fn applying_the_same_desired_state_twice_changes_nothing() {
+        let mut display = display();
+        let desired = DesiredDisplay {
+            layout: Some(Layout::Columns),
+            inner_gap: Some(5),
+            ..Default::default()
+        };
+
+        assert!(display.apply_desired(desired.clone()));
+        assert_eq!(display.layout, Layout::Columns);
+        assert_eq!(display.inner_gap, 5);
+        assert!(!display.apply_desired(desired));
+    }
+
+    #[test]
+    fn layout_rules_skip_avoided_layouts() {
+        let columns = LayoutRule {
+            exe: "obs64.exe".to_string(),
+            layout: Some(Layout::Columns),
+            ..Default::default()
+        };
+        let no_monocle = LayoutRule {
+            exe: "game.exe".to_string(),
+            avoid: vec![Layout::Monocle],
+            ..Default::default()
+        };
+
+        assert_eq!(ruled_layout(Layout::BSPV, &[]), Layout::BSPV);
+        assert_eq!(ruled_layout(Layout::BSPV, &[&columns]), Layout::Columns);
+        assert_eq!(ruled_layout(Layout::Monocle, &[&no_monocle]), Layout::BSPV);
+        assert_eq!(
+            ruled_layout(Layout::Monocle, &[&no_monocle, &columns]),
+            Layout::Columns
+        );
+    }
+
+    fn overlaps(a: Rect, b: Rect) -> bool {
+        a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height
+    }
+
+    fn within(rect: Rect, area: Rect) -> bool {
+        rect.width >= 0
+            && rect.height >= 0
+            && rect.x >= area.x
+            && rect.y >= area.y
+            && rect.x + rect.width <= area.x + area.width
+            && rect.y + rect.height <= area.y + area.height
+    }
+
+    /// Whether there are at least `gap` pixels between `a` and `b` along one
+    /// of the axes
+    fn separated(a: Rect, b: Rect, gap: i32) -> bool {
+        a.x + a.width + gap <= b.x
+            || b.x + b.width + gap <= a.x
+            || a.y + a.height + gap <= b.y
+            || b.y + b.height + gap <= a.y
+    }
+
+    fn total_area(rects: &[Rect]) -> i64 {
+        rects
+            .iter()
+            .map(|r| i64::from(r.width) * i64::from(r.height))
+            .sum()
+    }
+
+    /// Checks that `rects` don't overlap and cover all of `area` between them
+    fn assert_tiles(rects: &[Rect], area: Rect) -> Result<(), TestCaseError> {
+        for (i, a) in rects.iter().enumerate() {
+            prop_assert!(within(*a, area), "{:?} is outside of {:?}", a, area);
+
+            for b in &rects[i + 1..] {
+                prop_assert!(!overlaps(*a, *b), "{:?} overlaps {:?}", a, b);
+            }
+        }
+
+        if !rects.is_empty() {
+            prop_assert_eq!(total_area(rects), total_area(&[area]));
+        }
+
+        Ok(())
+    }
+
+    fn any_area() -> impl Strategy<Value = Rect> {
+        (-2000..2000, -2000..2000, 1000..4000, 1000..4000).prop_map(|(x, y, width, height)| Rect {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    fn any_resize() -> impl Strategy<Value = Rect> {
+        (-500..500, -500..500, -500..500, -500..500).prop_map(|(x, y, width, height)| Rect {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    fn any_layout() -> impl Strategy<Value = Layout> {
+        prop_oneof![
+            Just(Layout::BSPV),
+            Just(Layout::BSPH),
+            Just(Layout::Columns),
+            Just(Layout::Rows),
+            Just(Layout::Centred),
+            Just(Layout::Monocle),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn bsp_tiles_the_whole_area(area in any_area(), count in 0..12_usize, vertical in 0..2_usize) {
+            let rects = bsp(0, count, area, vertical, vec![]);
+
+            prop_assert_eq!(rects.len(), count);
+            assert_tiles(&rects, area)?;
+        }
+
+        #[test]
+        fn resized_bsp_stays_on_the_display(
+            area in any_area(),
+            count in 1..12_usize,
+            vertical in 0..2_usize,
+            resizes in vec_of(option::of(any_resize()), 12),
+        ) {
+            let rects = bsp(0, count, area, vertical, resizes);
+
+            prop_assert_eq!(rects.len(), count);
+            for rect in rects {
+                prop_assert!(within(rect.clamp_to(area), area));
+            }
+        }
+
+        #[test]
+        fn lanes_tile_the_whole_area(
+            area in any_area(),
+            len in 0..24_usize,
+            count in 0..8_usize,
+            columns in any::<bool>(),
+        ) {
+            let rects = lanes(area, len, count, columns);
+
+            prop_assert_eq!(rects.len(), len);
+            assert_tiles(&rects, area)?;
+        }
+
+        #[test]
+        fn centred_tiles_the_whole_area(area in any_area(), len in 3..12_usize, percent in 1..=100) {
+            let rects = centred(area, len, percent);
+
+            prop_assert_eq!(rects.len(), len);
+            assert_tiles(&rects, area)?;
+        }
+
+        #[test]
+        fn zoomed_windows_grow_without_leaving_the_display(
+            area in any_area(),
+            len in 1..8_usize,
+            percent in 100..300,
+        ) {
+            for rect in lanes(area, len, 0, true) {
+                let zoomed = zoom(rect, percent, area);
+
+                prop_assert!(within(zoomed, area));
+                prop_assert!(zoomed.width >= rect.width && zoomed.height >= rect.height);
+            }
+        }
+
+        #[test]
+        fn layouts_keep_the_gaps(
+            area in any_area(),
+            count in 1..9_isize,
+            inner_gap in 0..50,
+            outer_gap in 0..50,
+            layout in any_layout(),
+        ) {
+            let mut display = display();
+            display.dimensions = area;
+            display.layout = layout;
+            display.inner_gap = inner_gap;
+            display.outer_gap = outer_gap;
+            display.windows = (0..count).map(window).collect();
+            display.calculate_layout();
+
+            let rects = &display.layout_dimensions;
+            let expected = if layout == Layout::Monocle { 1 } else { count as usize };
+            prop_assert_eq!(rects.len(), expected);
+
+            for (i, a) in rects.iter().enumerate() {
+                prop_assert!(within(*a, display.get_dimensions()));
+
+                for b in &rects[i + 1..] {
+                    prop_assert!(separated(*a, *b, inner_gap), "{:?} is too close to {:?}", a, b);
+                }
+            }
+        }
     }
 }
@@ -2,67 +2,126 @@ extern crate flexi_logger;
 #[macro_use] extern crate num_derive;
 extern crate num_traits;
 
-use core::mem;
 use std::{
     borrow::BorrowMut,
-    collections::HashMap,
-    io::{BufRead, BufReader, ErrorKind},
+    io::{ErrorKind, Write},
+    path::PathBuf,
     process::exit,
-    str::FromStr,
     sync::{Arc, Mutex},
     thread,
+    time::{Duration, Instant},
 };
 
-use anyhow::{Context, Result};
-use crossbeam_channel::{select, unbounded, Receiver, Sender};
+use anyhow::{bail, Context, Result};
+use clap::Clap;
+use crossbeam_channel::{at, bounded, never, select, tick, unbounded, Receiver, Sender};
 use flexi_logger::{colored_detailed_format, Duplicate};
 use lazy_static::lazy_static;
-use log::{error, info};
+use log::{debug, error, info};
 use sysinfo::SystemExt;
-use uds_windows::UnixListener;
-
-use bindings::Windows::Win32::{
-    Foundation::POINT,
-    UI::WindowsAndMessaging::{GetCursorPos, HWND_TOP, SWP_NOMOVE, SWP_NOSIZE},
+use uds_windows::{UnixListener, UnixStream};
+
+use yatta_core::{
+    state::{DesiredState, State},
+    CycleDirection,
+    Layout,
+    OperationDirection,
+    Query,
+    Rules,
+    Sizing,
+    SocketMessage,
 };
-use yatta_core::{CycleDirection, Layout, OperationDirection, ResizeEdge, Sizing, SocketMessage};
 
 use crate::{
+    automation::Automations,
+    config::Config,
     desktop::{Desktop, Display},
-    rect::Rect,
-    window::exe_name_from_path,
+    metrics::{Metrics, StartupProfile},
+    status_window::StatusWindow,
+    subscribers::Subscribers,
+    throttle::{Coalesce, RateLimit},
     windows_event::{WindowsEvent, WindowsEventListener, WindowsEventType},
 };
 
+mod automation;
+mod config;
 mod desktop;
 mod message_loop;
+mod metrics;
+mod placement;
+mod quirks;
 mod rect;
+mod socket;
+mod state;
+mod status_window;
+mod subscribers;
+mod throttle;
+mod uia;
 mod window;
 mod windows_event;
 
 lazy_static! {
     static ref YATTA_CHANNEL: Arc<Mutex<(Sender<Message>, Receiver<Message>)>> =
         Arc::new(Mutex::new(unbounded()));
-    static ref FLOAT_CLASSES: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
-    static ref FLOAT_EXES: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
-    static ref FLOAT_TITLES: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
-    static ref DESKTOP_EXES: Arc<Mutex<HashMap<String, usize>>> =
-        Arc::new(Mutex::new(HashMap::new()));
-    static ref LAST_LAYOUT: Arc<Mutex<Layout>> = Arc::new(Mutex::new(Layout::BSPV));
-    static ref LAYERED_EXE_WHITELIST: Vec<String> = vec!["steam.exe".to_string()];
-    // Can be set to lower than 20, but it won't scale evenly (yet)
-    static ref PADDING: Arc<Mutex<i32>> = Arc::new(Mutex::new(20));
 }
 
 #[derive(Clone, Debug)]
 pub enum Message {
     WindowsEvent(WindowsEvent),
+    Command(SocketMessage),
+    /// A query along with where to send the response
+    Query(Query, Sender<String>),
+    /// Displays have been connected, disconnected or changed resolution
+    DisplayChange,
+    /// Where to send events for a `yattac subscribe` connection
+    Subscribe(Sender<String>),
+}
+
+// A yattac client that stops partway through a message for this long is
+// disconnected, while connections can stay open and idle between messages
+const SOCKET_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Gap adjustments that arrive within this time of each other are laid out
+// together, so that holding down a key doesn't make the windows flicker
+const PENDING_LAYOUT_DELAY: Duration = Duration::from_millis(30);
+/// How long a transaction can be left open before it is ended anyway, in case
+/// whatever began it never ends it
+const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clap)]
+#[clap(version = "1.0", author = "Jade I. <jadeiqbal@fastmail.com>")]
+struct Opts {
+    /// Path to a yaml configuration file to load on startup (default:
+    /// ~/.config/yatta/yatta.yaml, if it exists)
+    #[clap(long, parse(from_os_str))]
+    config:          Option<PathBuf>,
+    /// Log level for the log file (error, warn, info, debug, trace)
+    #[clap(long, default_value = "debug")]
+    log_level:       String,
+    /// Path of the socket to listen for yattac messages on (default:
+    /// ~/yatta.sock)
+    #[clap(long, parse(from_os_str))]
+    socket:          Option<PathBuf>,
+    /// Don't tile windows that are already open when yatta starts
+    #[clap(long)]
+    no_auto_manage:  bool,
+    /// Start paused, resume with yattac toggle-pause
+    #[clap(long)]
+    paused:          bool,
+    /// Only accept yattac messages that come after the token written next to
+    /// the socket (e.g. ~/yatta.sock.token)
+    #[clap(long)]
+    require_token:   bool,
+    /// Log how long each phase of starting up takes
+    #[clap(long)]
+    profile_startup: bool,
 }
 
 fn main() -> Result<()> {
+    let opts: Opts = Opts::parse();
     let home = dirs::home_dir().context("could not look up home directory")?;
 
-    flexi_logger::Logger::with_str("debug")
+    flexi_logger::Logger::with_str(&opts.log_level)
         .format(colored_detailed_format)
         .log_to_file()
         .o_timestamp(false)
@@ -75,41 +134,68 @@ fn main() -> Result<()> {
         .duplicate_to_stdout(Duplicate::Info)
         .start()?;
 
+    let mut startup = StartupProfile::start();
+
     let mut system = sysinfo::System::new_all();
     system.refresh_processes();
+    startup.phase_done("process scan");
 
     if system.get_process_by_name("yatta.exe").len() > 1 {
         error!("yatta.exe is already running, please exit the existing process before starting a new one");
         exit(1);
     }
 
-    let desktop: Arc<Mutex<Desktop>> = Arc::new(Mutex::new(Desktop::default()));
+    // yattac start doesn't pass any flags, so settings that should survive a
+    // restart go in a config file in the default location
+    let config_path = opts.config.clone().or_else(|| {
+        let path = home.join(".config").join("yatta").join("yatta.yaml");
+        Option::from(path).filter(|path| path.is_file())
+    });
+
+    let mut config = match &config_path {
+        Some(path) => {
+            let config = Config::load(path)?;
+            info!("loaded configuration from {}", path.display());
+            config
+        }
+        None => Config::default(),
+    };
+
+    // Command-line flags take precedence over the config file
+    config.start_paused |= opts.paused;
+    config.initial_tile &= !opts.no_auto_manage;
+
+    quirks::set(&config.quirks);
+    startup.phase_done("configuration");
+
+    // The Desktop is owned exclusively by the main loop below; the event listener
+    // and socket threads only ever talk to it through YATTA_CHANNEL
+    let mut desktop = Desktop::new(&config, &mut startup);
+    if config.start_paused {
+        info!("starting paused, no windows will be moved until tiling is resumed");
+    }
     info!("started yatta");
 
-    let listener = Arc::new(Mutex::new(WindowsEventListener::default()));
-    listener.lock().unwrap().start();
+    let listener = WindowsEventListener::default();
+    update_listener_rules(&listener, &desktop);
+    listener.start(config.events.clone());
 
-    let mut socket = home;
-    socket.push("yatta.sock");
+    if config.uia_discovery_poll_ms > 0 {
+        uia::start(config.uia_discovery_poll_ms);
+    }
+
+    let socket = opts.socket.unwrap_or_else(|| {
+        let mut socket = home;
+        socket.push("yatta.sock");
+        socket
+    });
     let socket = socket.as_path();
 
-    match std::fs::remove_file(&socket) {
-        Ok(_) => {}
-        Err(error) => match error.kind() {
-            // Doing this because ::exists() doesn't work reliably on Windows via IntelliJ
-            ErrorKind::NotFound => {}
-            _ => {
-                panic!("{}", error);
-            }
-        },
-    }
+    socket::claim(socket, &system)?;
+    let token = socket::token(socket, opts.require_token)?;
 
-    let stream = match UnixListener::bind(&socket) {
-        Err(error) => {
-            panic!("failed to bind socket: {}", error)
-        }
-        Ok(stream) => stream,
-    };
+    let stream = UnixListener::bind(socket)
+        .with_context(|| format!("could not bind socket {}", socket.display()))?;
 
     info!(
         "listening for yattac messages on socket: {}",
@@ -118,271 +204,212 @@ fn main() -> Result<()> {
             .context("could not convert socket path to string")?
     );
 
-    let desktop_clone = desktop.clone();
     thread::spawn(move || {
         for client in stream.incoming() {
             match client {
                 Ok(stream) => {
-                    let ls = Arc::clone(&listener);
-                    handle_socket_message(stream, &desktop_clone, ls);
+                    let token = token.clone();
+                    thread::spawn(move || {
+                        if let Err(error) = handle_socket_connection(stream, token) {
+                            error!("{:#}", error);
+                        }
+                    });
                 }
-                Err(err) => {
-                    println!("Error: {}", err);
+                Err(error) => {
+                    error!("could not accept yattac connection: {}", error);
                     break;
                 }
             }
         }
     });
 
+    let mut status_window = match StatusWindow::create(config.screen_reader) {
+        Ok(status_window) => Option::from(status_window),
+        Err(error) => {
+            error!("{}", error);
+            None
+        }
+    };
+
+    startup.phase_done("listeners and socket");
+    if opts.profile_startup {
+        for line in startup.report() {
+            info!("startup: {}", line);
+        }
+    }
+
+    let mut metrics = Metrics::new(config.slow_handler_threshold_ms);
     let yatta_receiver = YATTA_CHANNEL.lock().unwrap().1.clone();
 
+    let mut automations = Automations::new(config.automations.clone());
+    let automations_ticker = tick(Duration::from_secs(60));
+    run_automations(&mut automations, &mut desktop, &listener);
+
+    let work_area_ticker = match config.work_area_poll_secs {
+        0 => never(),
+        secs => tick(Duration::from_secs(secs)),
+    };
+
+    // When the pending gap adjustments have to be applied by, or when an open
+    // transaction times out
+    let mut layout_deadline: Option<Instant> = None;
+    let mut coalesce = Coalesce::default();
+    let mut subscribers = Subscribers::default();
+
     loop {
+        let pending_layouts = match layout_deadline {
+            Some(deadline) => at(deadline),
+            None => never(),
+        };
+        let delayed_shows = match desktop.next_show_due() {
+            Some(due) => at(due),
+            None => never(),
+        };
+
         select! {
                 recv(yatta_receiver) -> maybe_msg => {
                     let msg = maybe_msg.unwrap();
                     let _ = match msg {
+                        Message::WindowsEvent(ev) if ev.event_type == WindowsEventType::Show && desktop.delay_show(&ev) => {}
                         Message::WindowsEvent(ev) => {
-                            let ws = Arc::clone(&desktop) ;
-                            handle_windows_event_message(ev, ws)
-                        },
-                };
-            }
-        }
-    }
-}
-
-fn handle_windows_event_message(mut ev: WindowsEvent, desktop: Arc<Mutex<Desktop>>) {
-    let mut desktop = desktop.lock().unwrap();
-    if desktop.paused {
-        return;
-    }
-
-    // Make sure we discard any windows that no longer exist
-    for display in &mut desktop.displays {
-        display.windows.retain(|x| x.is_window());
-    }
-
-    let display_idx = desktop.get_active_display_idx();
-    let display = desktop.displays[display_idx].borrow_mut();
-
-    info!(
-        "handling yatta channel message: {} ({})",
-        ev.event_type, ev.event_code
-    );
+                            let started = Instant::now();
+                            let (handler, event_type, window) = (ev.event_type.to_string(), ev.event_type, ev.window);
+                            let windows_changed = matches!(
+                                event_type,
+                                WindowsEventType::Show | WindowsEventType::Hide | WindowsEventType::Destroy
+                            );
+                            handle_windows_event_message(ev, &mut desktop);
+                            metrics.record(&handler, started.elapsed(), Option::from(window));
 
-    match ev.event_type {
-        WindowsEventType::MoveResizeStart => {
-            let idx = ev.window.index(&display.windows);
-            let old_position = display.layout_dimensions[idx.unwrap_or(0)];
-            ev.window.set_pos(
-                old_position,
-                Option::from(HWND_TOP),
-                Option::from(SWP_NOMOVE | SWP_NOSIZE),
-            )
-        }
-        WindowsEventType::MoveResizeEnd => {
-            let idx = ev.window.index(&display.windows).unwrap_or(0);
-            let old_position = display.layout_dimensions[idx];
-            let new_position = ev.window.info().window_rect;
-
-            let mut resize = Rect::zero();
-            resize.x = new_position.x - old_position.x;
-            resize.y = new_position.y - old_position.y;
-            resize.width = new_position.width - old_position.width;
-            resize.height = new_position.height - old_position.height;
-
-            let is_move = resize.width == 0 && resize.height == 0;
-
-            if is_move {
-                info!("handling move event");
-                let mut target_window_idx = None;
-                let cursor_pos: POINT = unsafe {
-                    let mut cursor_pos: POINT = mem::zeroed();
-                    GetCursorPos(&mut cursor_pos);
-                    cursor_pos
+                            if windows_changed {
+                                automations.track(event_type, &window);
+                                run_automations(&mut automations, &mut desktop, &listener);
+                            }
+                        }
+                        Message::Command(msg) => {
+                            let started = Instant::now();
+                            if coalesce.allow(&msg, started) {
+                                let handler = msg.to_string();
+                                handle_command(msg, &mut desktop, &listener);
+                                metrics.record(&handler, started.elapsed(), None);
+                                coalesce.handled(Instant::now());
+                            }
+                        }
+                        Message::Query(query, response) => handle_query(query, &response, &desktop, &config, &metrics),
+                        Message::DisplayChange => {
+                            info!("displays changed, reconfiguring");
+                            desktop.reconfigure(&config);
+                        }
+                        Message::Subscribe(sender) => subscribers.add(sender, &desktop),
                 };
-
-                for (i, window) in display.windows.iter().enumerate() {
-                    if window.hwnd != ev.window.hwnd
-                        && display.layout_dimensions[i].contains_point((cursor_pos.x, cursor_pos.y))
-                    {
-                        target_window_idx = Option::from(i)
-                    }
+            }
+            recv(delayed_shows) -> _ => {
+                let shows = desktop.due_shows(Instant::now());
+                let shown = !shows.is_empty();
+                for ev in shows {
+                    let started = Instant::now();
+                    let (handler, window) = (ev.event_type.to_string(), ev.window);
+                    handle_windows_event_message(ev, &mut desktop);
+                    metrics.record(&handler, started.elapsed(), Option::from(window));
+                    automations.track(WindowsEventType::Show, &window);
                 }
 
-                if let Some(new_idx) = target_window_idx {
-                    let window_resize = display.windows[idx].resize;
-                    let new_window_resize = display.windows[new_idx].resize;
-
-                    {
-                        let window = display.windows[idx].borrow_mut();
-                        window.resize = new_window_resize;
-                    }
-
-                    {
-                        let new_window = display.windows[new_idx].borrow_mut();
-                        new_window.resize = window_resize;
-                    }
-
-                    display.windows.swap(idx, new_idx);
+                if shown {
+                    run_automations(&mut automations, &mut desktop, &listener);
                 }
-            } else {
-                info!("handling resize event");
-                let mut ops = vec![];
-
-                if resize.x != 0 {
-                    resize.x *= 2;
-                    let sizing = if resize.x > 0 {
-                        Sizing::Decrease
-                    } else {
-                        Sizing::Increase
-                    };
-
-                    ops.push((ResizeEdge::Left, sizing, resize.x.abs()))
+            }
+            recv(automations_ticker) -> _ => run_automations(&mut automations, &mut desktop, &listener),
+            recv(work_area_ticker) -> _ => {
+                if desktop.work_areas_changed() {
+                    info!("work area changed without an event, reconfiguring");
+                    desktop.reconfigure(&config);
                 }
-
-                if resize.y != 0 {
-                    resize.y *= 2;
-                    let sizing = if resize.y > 0 {
-                        Sizing::Decrease
-                    } else {
-                        Sizing::Increase
-                    };
-
-                    ops.push((ResizeEdge::Top, sizing, resize.y.abs()))
+            }
+            recv(pending_layouts) -> _ => {
+                if matches!(desktop.transaction, Some(began) if began.elapsed() >= TRANSACTION_TIMEOUT) {
+                    error!("ending a transaction that was left open");
+                    desktop.end_transaction();
                 }
 
-                if resize.width != 0 && resize.x == 0 {
-                    resize.width *= 2;
-                    let sizing = if resize.width > 0 {
-                        Sizing::Increase
-                    } else {
-                        Sizing::Decrease
-                    };
+                desktop.apply_pending_layouts();
+                layout_deadline = None;
+            }
+        }
 
-                    ops.push((ResizeEdge::Right, sizing, resize.width.abs()))
-                }
+        if layout_deadline.is_none() {
+            if desktop.has_pending_layouts() {
+                layout_deadline = Option::from(Instant::now() + PENDING_LAYOUT_DELAY);
+            } else if let Some(began) = desktop.transaction {
+                layout_deadline = Option::from(began + TRANSACTION_TIMEOUT);
+            }
+        }
 
-                if resize.height != 0 && resize.y == 0 {
-                    resize.height *= 2;
-                    let sizing = if resize.height > 0 {
-                        Sizing::Increase
-                    } else {
-                        Sizing::Decrease
-                    };
+        if let Some(status_window) = &mut status_window {
+            status_window.update(&desktop);
+        }
 
-                    ops.push((ResizeEdge::Bottom, sizing, resize.height.abs()))
-                }
+        subscribers.publish(&desktop);
+    }
+}
 
-                for (edge, sizing, step) in ops {
-                    display.resize_window(edge, sizing, Option::from(step));
-                }
+/// Grows or shrinks `gap` by `step`, which can be negative when it comes from a
+/// client, without ever going below 0
+fn adjust_gap(gap: i32, sizing: Sizing, step: i32) -> i32 {
+    let gap = match sizing {
+        Sizing::Increase => gap.saturating_add(step),
+        Sizing::Decrease => gap.saturating_sub(step),
+    };
 
-                display.calculate_layout();
-            }
+    gap.max(0)
+}
 
-            display.apply_layout(None);
-        }
-        WindowsEventType::Show => {
-            if display.windows.is_empty() {
-                display.windows.push(ev.window);
-                display.calculate_layout();
-                display.apply_layout(None);
-            } else {
-                // Some apps like Windows Terminal send multiple Events on startup, we don't
-                // want dupes
-                let mut contains = false;
-
-                for window in &display.windows {
-                    if window.hwnd == ev.window.hwnd {
-                        contains = true;
-                    }
-                }
+/// Sends the commands of any automations that have started or stopped applying
+fn run_automations(
+    automations: &mut Automations,
+    desktop: &mut Desktop,
+    listener: &WindowsEventListener,
+) {
+    for msg in automations.evaluate() {
+        handle_command(msg, desktop, listener);
+    }
+}
 
-                if !contains {
-                    let idx = display.get_foreground_window_index() + 1;
-                    // If we are inserting where there is a window that has resize adjustments, take
-                    // over those resize adjustments and remove them from the window that is
-                    // currently there
-                    if let Some(current_window) = display.windows.get_mut(idx) {
-                        let resize = current_window.resize;
-                        current_window.resize = None;
-                        ev.window.resize = resize;
-                    }
+/// Handles a command from yattac or an automation, and lets the event listener
+/// know about any rules that it changed
+fn handle_command(msg: SocketMessage, desktop: &mut Desktop, listener: &WindowsEventListener) {
+    let rules_changed = matches!(
+        msg,
+        SocketMessage::ImportRules(_)
+            | SocketMessage::FloatTitle(_)
+            | SocketMessage::ApplyState(DesiredState { rules: Some(_), .. })
+    );
 
-                    display.windows.insert(idx, ev.window);
-                    display.calculate_layout();
-                    display.apply_layout(None);
-
-                    if let Some(title) = ev.window.title() {
-                        if let Ok(path) = ev.window.exe_path() {
-                            info!(
-                                "managing new window: {} - {} ({})",
-                                &exe_name_from_path(&path),
-                                &title,
-                                ev.window.hwnd.0
-                            );
-                        }
-                    }
-                }
-            }
-        }
-        WindowsEventType::Hide | WindowsEventType::Destroy => {
-            let idx = ev.window.index(&display.windows);
-            let mut previous = idx.unwrap_or(0);
-            let mut next = idx.unwrap_or(0);
-            previous = if previous == 0 { 0 } else { previous - 1 };
-            next = if next == 0 { 0 } else { next + 1 };
-
-            // If we are removing a window that has resize adjustments, take over those
-            // resize adjustments and add them from the window that is going to take the
-            // space of the window being removed
-            let resize = if let Some(current_window) = display.windows.get(idx.unwrap_or(0)) {
-                current_window.resize
-            } else {
-                None
-            };
-
-            if let Some(next_window) = display.windows.get_mut(next) {
-                next_window.resize = resize;
-            }
+    handle_socket_message(msg, desktop);
 
-            display.windows.retain(|x| !ev.window.eq(x));
-            display.calculate_layout();
-            display.apply_layout(Option::from(previous));
-            if let Some(title) = ev.window.title() {
-                info!("unmanaging window: {} ({})", &title, ev.window.hwnd.0);
-            }
-        }
-        WindowsEventType::FocusChange => {
-            let mut contains = false;
+    if rules_changed {
+        update_listener_rules(listener, desktop);
+    }
+}
 
-            for window in &display.windows {
-                if window.hwnd == ev.window.hwnd {
-                    contains = true;
-                }
-            }
+/// Events that the listener only sends for some windows depend on the rules
+fn update_listener_rules(listener: &WindowsEventListener, desktop: &Desktop) {
+    listener.set_name_change_on_launch_exes(desktop.name_change_on_launch_exes());
+    listener.set_title_rules(desktop.has_title_rules());
+}
 
-            // Only operate on windows we are tracking, some apps like explorer.exe send
-            // a focus change event before their show event
-            if contains {
-                display.calculate_layout();
-                display.apply_layout(None);
-
-                display.foreground_window = ev.window;
-                if let Some(title) = ev.window.title() {
-                    if let Ok(path) = ev.window.exe_path() {
-                        info!(
-                            "focusing window: {} - {} ({})",
-                            &exe_name_from_path(&path),
-                            &title,
-                            ev.window.hwnd.0
-                        );
-                    }
-                }
-            }
-        }
+fn handle_windows_event_message(ev: WindowsEvent, desktop: &mut Desktop) {
+    if desktop.paused {
+        return;
     }
+
+    // Make sure we discard any windows that no longer exist or have been hidden.
+    // The window the event is for is left to the event, so that a closed window
+    // is unmanaged like any other, and a title change only concerns that window.
+    if ev.event_type != WindowsEventType::TitleChange {
+        desktop.prune_windows(&ev.window);
+    }
+
+    desktop.handle_windows_event(ev);
 }
 
 pub enum DirectionOperation {
@@ -399,254 +426,428 @@ impl DirectionOperation {
                 }
             }
             DirectionOperation::Move => {
-                let window_resize = display.windows[idx].resize;
-                let new_window_resize = display.windows[new_idx].resize;
+                display.swap_windows(idx, new_idx);
+                display.calculate_layout();
+                display.apply_layout(Option::from(new_idx));
+            }
+        }
+
+        display.follow_focus_with_mouse(new_idx);
+    }
+}
 
-                {
-                    let window = display.windows[idx].borrow_mut();
-                    window.resize = new_window_resize;
+/// Handles every message sent on a yattac connection. When yatta has a `token`,
+/// nothing but handshakes is accepted until the connection has presented it.
+fn handle_socket_connection(stream: UnixStream, token: Option<String>) -> Result<()> {
+    stream
+        .set_read_timeout(Option::from(SOCKET_READ_TIMEOUT))
+        .context("could not set read timeout on yattac connection")?;
+
+    let yatta_sender = YATTA_CHANNEL.lock().unwrap().0.clone();
+
+    let mut writer = stream
+        .try_clone()
+        .context("could not clone yattac connection for writing")?;
+
+    // Parse everything the client sends before it reaches the Desktop, so that a
+    // slow or stalled client never holds up window management
+    let mut reader = yatta_core::read_messages(stream).wait_while_idle();
+    let mut authenticated = token.is_none();
+    let mut rate_limit = RateLimit::new();
+    while let Some(message) = reader.next() {
+        let (message, msg) = match message {
+            Ok(message) => message,
+            Err(error) if matches!(error.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+                debug!("closing yattac connection that stalled partway through a message");
+                return Ok(());
+            }
+            Err(error) => return Err(error).context("could not read yattac socket message"),
+        };
+        match msg {
+            Ok(SocketMessage::Authenticate(presented)) => {
+                if !authenticated && Option::from(&presented) != token.as_ref() {
+                    bail!("closing yattac connection that presented the wrong token");
                 }
 
-                {
-                    let new_window = display.windows[new_idx].borrow_mut();
-                    new_window.resize = window_resize;
+                authenticated = true;
+
+                let response = reader
+                    .format
+                    .encode(&String::from("authenticated"))
+                    .context("could not encode yattac authentication response")?;
+
+                writer
+                    .write_all(&response)
+                    .context("could not write yattac authentication response")?;
+            }
+            Ok(ref msg) if !authenticated && !matches!(msg, SocketMessage::Handshake(_)) => {
+                bail!(
+                    "closing yattac connection that sent {} without presenting the token",
+                    msg
+                );
+            }
+            // Messages that are answered are never dropped, the client would be left
+            // waiting for a response that doesn't come
+            Ok(ref msg) if !msg.has_response() && !rate_limit.allow(Instant::now()) => {}
+            Ok(SocketMessage::Query(query)) => {
+                let (sender, receiver) = bounded(1);
+                yatta_sender
+                    .send(Message::Query(query, sender))
+                    .context("could not send yattac query to yatta channel")?;
+
+                let response = receiver
+                    .recv_timeout(SOCKET_READ_TIMEOUT)
+                    .context("timed out waiting for a response to yattac query")?;
+
+                let response = reader
+                    .format
+                    .encode(&response)
+                    .context("could not encode yattac query response")?;
+
+                writer
+                    .write_all(&response)
+                    .context("could not write yattac query response")?;
+            }
+            Ok(SocketMessage::Subscribe) => {
+                let (sender, receiver) = unbounded();
+                yatta_sender
+                    .send(Message::Subscribe(sender))
+                    .context("could not send yattac subscription to yatta channel")?;
+
+                info!("streaming events to a yattac subscriber");
+
+                // The connection only carries events from here on, until the
+                // subscriber goes away and the next write fails
+                for event in receiver {
+                    let event = reader
+                        .format
+                        .encode(&event)
+                        .context("could not encode event for yattac subscriber")?;
+
+                    if writer.write_all(&event).is_err() {
+                        break;
+                    }
                 }
 
-                display.windows.swap(idx, new_idx);
-                display.calculate_layout();
-                display.apply_layout(Option::from(new_idx));
+                return Ok(());
             }
-        }
+            Ok(SocketMessage::Handshake(format)) => {
+                let response = reader
+                    .format
+                    .encode(&format.to_string())
+                    .context("could not encode yattac handshake response")?;
 
-        display.follow_focus_with_mouse(new_idx);
+                writer
+                    .write_all(&response)
+                    .context("could not write yattac handshake response")?;
+
+                reader.format = format;
+            }
+            Ok(msg) => yatta_sender
+                .send(Message::Command(msg))
+                .context("could not send yattac socket message to yatta channel")?,
+            Err(error) => error!(
+                "could not parse yattac socket message '{}': {}",
+                message, error
+            ),
+        }
     }
+
+    Ok(())
 }
 
-fn handle_socket_message(
-    stream: uds_windows::UnixStream,
-    desktop: &Arc<Mutex<Desktop>>,
-    _listener: Arc<Mutex<WindowsEventListener>>,
+fn handle_query(
+    query: Query,
+    response: &Sender<String>,
+    desktop: &Desktop,
+    config: &Config,
+    metrics: &Metrics,
 ) {
-    let mut desktop = desktop.lock().unwrap();
-
-    let stream = BufReader::new(stream);
-    for line in stream.lines() {
-        match line {
-            Ok(socket_msg) => {
-                if let Ok(msg) = SocketMessage::from_str(&socket_msg) {
-                    if desktop.paused && !matches!(msg, SocketMessage::TogglePause) {
-                        return;
-                    }
+    info!("handling yattac query: {}", query);
 
-                    let display_idx = desktop.get_active_display_idx();
-                    let d = desktop.displays[display_idx].borrow_mut();
+    let serialized = match query {
+        Query::State => {
+            serde_json::to_string_pretty(&State::from(desktop)).map_err(anyhow::Error::from)
+        }
+        Query::Displays => {
+            serde_json::to_string_pretty(&state::displays(desktop)).map_err(anyhow::Error::from)
+        }
+        Query::Windows => {
+            serde_json::to_string_pretty(&state::windows(desktop)).map_err(anyhow::Error::from)
+        }
+        Query::Focused => {
+            serde_json::to_string_pretty(&state::focused(desktop)).map_err(anyhow::Error::from)
+        }
+        Query::Config => {
+            serde_yaml::to_string(&desktop.effective_config(config)).map_err(anyhow::Error::from)
+        }
+        Query::Metrics => serde_json::to_string_pretty(metrics).map_err(anyhow::Error::from),
+        Query::Rules => serde_yaml::to_string(&desktop.rules).map_err(anyhow::Error::from),
+        Query::Managed(managed) => {
+            serde_json::to_string_pretty(&state::managed(desktop, managed.group_by))
+                .map_err(anyhow::Error::from)
+        }
+    };
 
-                    info!("handling yattac socket message: {:?}", &msg);
-                    match msg {
-                        SocketMessage::FocusWindow(direction) => match direction {
-                            OperationDirection::Left => d.window_op_left(DirectionOperation::Focus),
-                            OperationDirection::Right => {
-                                d.window_op_right(DirectionOperation::Focus)
-                            }
-                            OperationDirection::Up => d.window_op_up(DirectionOperation::Focus),
-                            OperationDirection::Down => d.window_op_down(DirectionOperation::Focus),
-                            OperationDirection::Previous => {
-                                d.window_op_previous(DirectionOperation::Focus)
-                            }
-                            OperationDirection::Next => d.window_op_next(DirectionOperation::Focus),
-                        },
-                        SocketMessage::Promote => {
-                            let idx = d.get_foreground_window_index();
-                            let window = d.windows.remove(idx);
-                            d.windows.insert(0, window);
-                            d.calculate_layout();
-                            d.apply_layout(Option::from(0));
-                            let window = d.windows.get(0).unwrap();
-                            window.set_cursor_pos(d.layout_dimensions[0]);
-                        }
-                        SocketMessage::TogglePause => {
-                            desktop.paused = !desktop.paused;
-                        }
-                        SocketMessage::ToggleMonocle => match d.layout {
-                            Layout::Monocle => {
-                                let idx = d.get_foreground_window_index();
-                                if let Some(window) = d.windows.get(idx) {
-                                    let window = *window;
-                                    let last_desktop = LAST_LAYOUT.lock().unwrap();
-                                    d.layout = *last_desktop;
-                                    d.calculate_layout();
-                                    d.apply_layout(None);
-
-                                    // If we have monocle'd a floating window, we want to restore it
-                                    // to the default floating position when toggling off monocle
-                                    if !window.tile {
-                                        let w2 = d.get_dimensions().width / 2;
-                                        let h2 = d.get_dimensions().height / 2;
-                                        let center = Rect {
-                                            x:      d.get_dimensions().x
-                                                + ((d.get_dimensions().width - w2) / 2),
-                                            y:      d.get_dimensions().y
-                                                + ((d.get_dimensions().height - h2) / 2),
-                                            width:  w2,
-                                            height: h2,
-                                        };
-                                        window.set_pos(center, None, None);
-                                        window.set_cursor_pos(center);
-                                    }
-                                }
-                            }
-                            _ => {
-                                let mut last_desktop = LAST_LAYOUT.lock().unwrap();
-                                *last_desktop = d.layout;
+    let serialized = match serialized {
+        Ok(serialized) => serialized,
+        Err(error) => {
+            error!("could not serialize response to yattac query: {}", error);
+            return;
+        }
+    };
 
-                                d.layout = Layout::Monocle;
-                                d.calculate_layout();
-                                d.apply_layout(None);
-                            }
-                        },
-                        SocketMessage::ToggleFloat => {
-                            let idx = d.get_foreground_window_index();
-                            let mut window = d.windows.remove(idx);
-                            window.toggle_float();
-                            d.windows.insert(idx, window);
-                            d.calculate_layout();
-                            d.apply_layout(None);
-
-                            // Centre the window if we have disabled tiling
-                            if !window.tile {
-                                let w2 = d.get_dimensions().width / 2;
-                                let h2 = d.get_dimensions().height / 2;
-                                let center = Rect {
-                                    x:      d.get_dimensions().x
-                                        + ((d.get_dimensions().width - w2) / 2),
-                                    y:      d.get_dimensions().y
-                                        + ((d.get_dimensions().height - h2) / 2),
-                                    width:  w2,
-                                    height: h2,
-                                };
-                                window.set_pos(center, None, None);
-                                window.set_cursor_pos(center);
-                            } else {
-                                // Make sure the mouse cursor goes back once we reenable tiling
-                                window.set_cursor_pos(d.layout_dimensions[idx]);
-                            }
-                        }
-                        SocketMessage::Retile => {
-                            // Retiling should also rebalance the layout by resetting resizing
-                            // adjustments
-                            for window in d.windows.iter_mut() {
-                                window.resize = None
-                            }
+    // The client may have given up waiting already
+    let _ = response.send(serialized);
+}
 
-                            d.get_foreground_window();
-                            d.calculate_layout();
-                            let idx = d.foreground_window.index(&d.windows);
-                            d.apply_layout(idx);
-                        }
-                        SocketMessage::MoveWindow(direction) => match direction {
-                            OperationDirection::Left => d.window_op_left(DirectionOperation::Move),
-                            OperationDirection::Right => {
-                                d.window_op_right(DirectionOperation::Move)
-                            }
-                            OperationDirection::Up => d.window_op_up(DirectionOperation::Move),
-                            OperationDirection::Down => d.window_op_down(DirectionOperation::Move),
-                            OperationDirection::Previous => {
-                                d.window_op_previous(DirectionOperation::Move)
-                            }
-                            OperationDirection::Next => d.window_op_next(DirectionOperation::Move),
-                        },
-                        SocketMessage::MoveWindowToDisplay(direction) => {
-                            let idx = d.get_foreground_window_index();
-                            desktop.move_window_to_display(idx, display_idx, direction);
-                        }
-                        SocketMessage::MoveWindowToDisplayNumber(target) => {
-                            let idx = d.get_foreground_window_index();
-                            desktop.move_window_to_display_number(idx, display_idx, target);
-                        }
-                        SocketMessage::FocusDisplay(direction) => {
-                            desktop.focus_display(display_idx, direction);
-                        }
-                        SocketMessage::FocusDisplayNumber(target) => {
-                            desktop.focus_display_number(target);
-                        }
-                        SocketMessage::ResizeWindow(edge, sizing) => {
-                            d.resize_window(edge, sizing, None);
-                            d.calculate_layout();
-                            d.apply_layout(None);
-                        }
-                        SocketMessage::GapSize(size) => {
-                            d.gaps = size;
-                            d.calculate_layout();
-                            d.apply_layout(None);
-                        }
-                        SocketMessage::PaddingSize(size) => {
-                            *PADDING.lock().unwrap() = size;
-                            d.calculate_layout();
-                            d.apply_layout(None);
-                        }
-                        SocketMessage::AdjustGaps(sizing) => {
-                            match sizing {
-                                Sizing::Increase => {
-                                    d.gaps += 1;
-                                }
-                                Sizing::Decrease => {
-                                    if d.gaps > 0 {
-                                        d.gaps -= 1;
-                                    }
-                                }
-                            }
+fn handle_socket_message(msg: SocketMessage, desktop: &mut Desktop) {
+    // Rules are still accepted while paused so that they can all be in place before
+    // tiling resumes
+    if desktop.paused
+        && !matches!(
+            msg,
+            SocketMessage::TogglePause
+                | SocketMessage::SetPause(_)
+                | SocketMessage::FloatClass(_)
+                | SocketMessage::FloatExe(_)
+                | SocketMessage::FloatTitle(_)
+                | SocketMessage::ImportRules(_)
+                | SocketMessage::ApplyState(_)
+                | SocketMessage::Stop
+                | SocketMessage::Suspend
+                | SocketMessage::Resume
+        )
+    {
+        return;
+    }
 
-                            d.calculate_layout();
-                            d.apply_layout(None);
-                        }
-                        SocketMessage::Layout(layout) => {
-                            // Layouts should always start in a balanced state
-                            for window in d.windows.iter_mut() {
-                                window.resize = None
-                            }
+    // Messages for a specific window act on the display that the window is on
+    let window = match msg.hwnd() {
+        Some(hwnd) => match desktop.find_window(hwnd) {
+            Some(window) => Option::from(window),
+            None => {
+                error!("window {} is not managed by yatta", hwnd);
+                return;
+            }
+        },
+        None => None,
+    };
+    let window_idx = window.map(|(_, window_idx)| window_idx);
+
+    let display_idx = match (window, msg.display()) {
+        (Some((display_idx, _)), _) => display_idx,
+        (None, Some(number)) => match desktop.get_display_idx(number) {
+            Some(idx) => idx,
+            None => {
+                error!("display {} does not exist", number);
+                return;
+            }
+        },
+        (None, None) => desktop.get_active_display_idx(),
+    };
+    let d = desktop.displays[display_idx].borrow_mut();
+
+    info!("handling yattac socket message: {:?}", &msg);
+    match msg {
+        SocketMessage::FocusWindow(direction) => match direction {
+            OperationDirection::Left
+            | OperationDirection::Right
+            | OperationDirection::Up
+            | OperationDirection::Down => desktop.focus_window_in_direction(display_idx, direction),
+            OperationDirection::Previous => d.window_op_previous(DirectionOperation::Focus),
+            OperationDirection::Next => d.window_op_next(DirectionOperation::Focus),
+        },
+        SocketMessage::FocusFloat(direction) => d.focus_float(direction),
+        SocketMessage::BeginTransaction => desktop.begin_transaction(),
+        SocketMessage::EndTransaction => desktop.end_transaction(),
+        SocketMessage::Promote => {
+            // With a master window, the best a window can be promoted to is the spot
+            // after it
+            let first = d.first_free_idx();
+            if let Some(idx) = d
+                .foreground_window
+                .index(&d.windows)
+                .filter(|idx| *idx >= first)
+            {
+                let window = d.windows.remove(idx);
+                d.windows.insert(first, window);
+                d.calculate_layout();
+                d.apply_layout(Option::from(first));
+                d.follow_focus_with_mouse(first);
+            }
+        }
+        SocketMessage::Stop => {
+            desktop.restore_windows();
+            info!("stopped yatta");
+            exit(0);
+        }
+        SocketMessage::Suspend => desktop.suspend(),
+        SocketMessage::Resume => desktop.resume(),
+        SocketMessage::TogglePause => {
+            desktop.set_paused(!desktop.paused);
+        }
+        SocketMessage::SetPause(paused) => {
+            desktop.set_paused(paused);
+        }
+        SocketMessage::ToggleMonocle => {
+            let monocle = matches!(d.layout, Layout::Monocle);
+            d.set_monocle(!monocle);
+        }
+        SocketMessage::SetMonocle(monocle) => {
+            d.set_monocle(monocle);
+        }
+        SocketMessage::ToggleZoomFocused => d.toggle_zoom(),
+        SocketMessage::ToggleMaster => d.toggle_master(),
+        SocketMessage::CycleTileOccupants(direction) => d.cycle_tile_occupants(direction),
+        SocketMessage::ToggleFloat => {
+            if let Some(idx) = d.foreground_window.index(&d.windows) {
+                let float = d.windows[idx].tile;
+                d.set_float(float);
+            }
+        }
+        SocketMessage::FloatAll => d.float_all(),
+        SocketMessage::TileAll => d.tile_all(),
+        SocketMessage::SetFloat(float) => {
+            d.set_float(float);
+        }
+        // Queries, handshakes, tokens and subscriptions are answered on the connection they
+        // came in on
+        SocketMessage::Query(_)
+        | SocketMessage::Handshake(_)
+        | SocketMessage::Authenticate(_)
+        | SocketMessage::Subscribe => {}
+        SocketMessage::Retile(_) => {
+            // Retiling should also rebalance the layout by resetting resizing
+            // adjustments, and give windows that couldn't be moved another chance
+            for window in d.windows.iter_mut() {
+                window.resize = None;
+                window.pos_failures = 0;
+            }
 
-                            d.layout = layout;
-                            d.calculate_layout();
-                            d.apply_layout(None);
-                        }
-                        SocketMessage::CycleLayout(direction) => {
-                            // Layouts should always start in a balanced state
-                            for window in d.windows.iter_mut() {
-                                window.resize = None
-                            }
+            d.get_foreground_window();
+            d.calculate_layout();
+            let idx = d.foreground_window.index(&d.windows);
+            d.apply_layout(idx);
+        }
+        SocketMessage::MoveWindow(direction) => match direction {
+            OperationDirection::Left
+            | OperationDirection::Right
+            | OperationDirection::Up
+            | OperationDirection::Down => {
+                d.window_op_in_direction(direction, DirectionOperation::Move)
+            }
+            OperationDirection::Previous => d.window_op_previous(DirectionOperation::Move),
+            OperationDirection::Next => d.window_op_next(DirectionOperation::Move),
+        },
+        SocketMessage::SwapWithFloat(direction) => d.swap_with_float(direction),
+        SocketMessage::MoveWindowToDisplay(direction) => {
+            if let Some(idx) = d.foreground_window.index(&d.windows) {
+                desktop.move_window_to_display(idx, display_idx, direction);
+            }
+        }
+        SocketMessage::MoveWindowToDisplayNumber(target) => {
+            if let Some(idx) = d.foreground_window.index(&d.windows) {
+                desktop.move_window_to_display_number(idx, display_idx, target);
+            }
+        }
+        SocketMessage::FocusDisplay(direction) => {
+            desktop.focus_display(display_idx, direction);
+        }
+        SocketMessage::FocusDisplayNumber(target) => {
+            desktop.focus_display_number(target);
+        }
+        SocketMessage::ResizeWindow(edge, sizing) => {
+            d.resize_window(edge, sizing, None);
+            d.calculate_layout();
+            d.apply_layout(None);
+        }
+        SocketMessage::SetInnerGap(size, _) => {
+            d.inner_gap = size;
+            d.calculate_layout();
+            d.apply_layout(None);
+        }
+        SocketMessage::SetOuterGap(size, _) => {
+            d.outer_gap = size;
+            d.calculate_layout();
+            d.apply_layout(None);
+        }
+        SocketMessage::SetColumns(count, _) => {
+            d.columns = count;
+            d.calculate_layout();
+            d.apply_layout(None);
+        }
+        SocketMessage::SetRows(count, _) => {
+            d.rows = count;
+            d.calculate_layout();
+            d.apply_layout(None);
+        }
+        // Holding down a key bound to these sends lots of them in a row, so the
+        // windows are only moved once the main loop gets round to it
+        SocketMessage::AdjustInnerGap(sizing, step) => {
+            d.inner_gap = adjust_gap(d.inner_gap, sizing, step);
+            d.layout_pending = true;
+        }
+        SocketMessage::AdjustOuterGap(sizing, step) => {
+            d.outer_gap = adjust_gap(d.outer_gap, sizing, step);
+            d.layout_pending = true;
+        }
+        SocketMessage::Layout(layout, _) => {
+            // Layouts should always start in a balanced state
+            for window in d.windows.iter_mut() {
+                window.resize = None
+            }
 
-                            match direction {
-                                CycleDirection::Previous => d.layout.previous(),
-                                CycleDirection::Next => d.layout.next(),
-                            }
+            d.set_layout(layout);
+            d.calculate_layout();
+            d.apply_layout(None);
+        }
+        SocketMessage::CycleLayout(direction, _) => {
+            // Layouts should always start in a balanced state
+            for window in d.windows.iter_mut() {
+                window.resize = None
+            }
 
-                            d.calculate_layout();
-                            d.apply_layout(None);
-                        }
-                        SocketMessage::FloatClass(target) => {
-                            let mut float_classes = FLOAT_CLASSES.lock().unwrap();
-                            if !float_classes.contains(&target) {
-                                float_classes.push(target)
-                            }
-                        }
-                        SocketMessage::FloatExe(target) => {
-                            let mut float_exes = FLOAT_EXES.lock().unwrap();
-                            if !float_exes.contains(&target) {
-                                float_exes.push(target)
-                            }
-                        }
-                        SocketMessage::FloatTitle(target) => {
-                            let mut float_titles = FLOAT_TITLES.lock().unwrap();
-                            if !float_titles.contains(&target) {
-                                float_titles.push(target)
-                            }
-                        }
-                    }
-                }
+            let mut layout = d.layout;
+            match direction {
+                CycleDirection::Previous => layout.previous(),
+                CycleDirection::Next => layout.next(),
             }
-            Err(error) => {
-                error!("{}", error);
+
+            d.set_layout(layout);
+
+            d.calculate_layout();
+            d.apply_layout(None);
+        }
+        SocketMessage::FocusWindowByHwnd(_) => {
+            if let Some(idx) = window_idx {
+                d.windows[idx].set_foreground();
+                d.follow_focus_with_mouse(idx);
+            }
+        }
+        SocketMessage::FloatWindowByHwnd(_, float) => {
+            if let Some(idx) = window_idx {
+                d.set_window_float(idx, float);
+            }
+        }
+        SocketMessage::MoveHwndToDisplayNumber(_, target) => {
+            if let Some(idx) = window_idx {
+                desktop.move_window_to_display_number(idx, display_idx, target);
             }
         }
+        SocketMessage::ImportRules(rules) => desktop.add_rules(rules),
+        SocketMessage::ApplyState(state) => desktop.apply_state(state),
+        SocketMessage::FloatClass(target) => desktop.add_rules(Rules {
+            float_classes: vec![target],
+            ..Default::default()
+        }),
+        SocketMessage::FloatExe(target) => desktop.add_rules(Rules {
+            float_exes: vec![target],
+            ..Default::default()
+        }),
+        SocketMessage::FloatTitle(target) => desktop.add_rules(Rules {
+            float_titles: vec![target],
+            ..Default::default()
+        }),
     }
 }
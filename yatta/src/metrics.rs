@@ -0,0 +1,108 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use log::warn;
+use serde::Serialize;
+
+use crate::window::Window;
+
+/// How long yatta has been spending on each kind of message, returned by
+/// `yattac query metrics`
+#[derive(Debug, Default, Serialize)]
+pub struct Metrics {
+    /// Handling a message for longer than this is logged as a warning
+    pub slow_threshold_ms: u64,
+    /// Keyed by windows event type or yattac message name
+    pub handlers:          HashMap<String, HandlerMetrics>,
+    /// Number of slow windows events caused by windows of each exe
+    pub slow_exes:         HashMap<String, u64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct HandlerMetrics {
+    pub count:      u64,
+    pub total_ms:   f64,
+    pub max_ms:     f64,
+    pub slow_count: u64,
+}
+
+impl Metrics {
+    pub fn new(slow_threshold_ms: u64) -> Self {
+        Metrics {
+            slow_threshold_ms,
+            ..Default::default()
+        }
+    }
+
+    /// Records that handling a message of kind `handler` took `elapsed`.
+    /// `window` is the window that a windows event was for.
+    pub fn record(&mut self, handler: &str, elapsed: Duration, window: Option<Window>) {
+        let ms = elapsed.as_secs_f64() * 1000.0;
+        let slow = elapsed >= Duration::from_millis(self.slow_threshold_ms);
+
+        let metrics = self.handlers.entry(handler.to_string()).or_default();
+        metrics.count += 1;
+        metrics.total_ms += ms;
+        metrics.max_ms = metrics.max_ms.max(ms);
+
+        if !slow {
+            return;
+        }
+
+        metrics.slow_count += 1;
+
+        match window {
+            Some(window) => {
+                let exe = window.exe_name();
+                warn!(
+                    "handling {} for {} ({}) took {:.1}ms",
+                    handler, exe, window.hwnd.0, ms
+                );
+
+                *self.slow_exes.entry(exe).or_default() += 1;
+            }
+            None => warn!("handling {} took {:.1}ms", handler, ms),
+        }
+    }
+}
+
+/// How long each phase of starting up took, logged with `yatta
+/// --profile-startup`
+#[derive(Debug)]
+pub struct StartupProfile {
+    phases: Vec<(&'static str, Duration)>,
+    last:   Instant,
+}
+
+impl StartupProfile {
+    pub fn start() -> Self {
+        StartupProfile {
+            phases: vec![],
+            last:   Instant::now(),
+        }
+    }
+
+    /// Records that `phase` has just finished, having started when the
+    /// previous phase finished
+    pub fn phase_done(&mut self, phase: &'static str) {
+        let now = Instant::now();
+        self.phases.push((phase, now - self.last));
+        self.last = now;
+    }
+
+    /// One line per phase in the order they ran, followed by the total
+    pub fn report(&self) -> Vec<String> {
+        let total: Duration = self.phases.iter().map(|(_, elapsed)| *elapsed).sum();
+
+        self.phases
+            .iter()
+            .map(|(phase, elapsed)| (*phase, *elapsed))
+            .chain(Some(("total", total)))
+            .map(|(phase, elapsed)| {
+                format!("{:<24}{:>10.1}ms", phase, elapsed.as_secs_f64() * 1000.0)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,56 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+use log::error;
+
+/// Saved in the home directory alongside the log file
+pub const PLACEMENT_HISTORY_FILE: &str = "yatta-placements.json";
+
+/// The display number each exe was last moved to, saved to disk after every
+/// change so that it survives restarts
+#[derive(Debug, Clone)]
+pub struct PlacementHistory {
+    path:     PathBuf,
+    displays: HashMap<String, usize>,
+}
+
+impl PlacementHistory {
+    /// Loads the history saved at `path`, starting from an empty history if
+    /// there is nothing there yet
+    pub fn load(path: PathBuf) -> Self {
+        let displays = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|error| {
+                error!(
+                    "could not parse placement history {}: {}",
+                    path.display(),
+                    error
+                );
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+
+        PlacementHistory { path, displays }
+    }
+
+    pub fn display_number(&self, exe: &str) -> Option<usize> {
+        self.displays.get(exe).copied()
+    }
+
+    pub fn record(&mut self, exe: String, display_number: usize) {
+        if self.displays.insert(exe, display_number) == Option::from(display_number) {
+            return;
+        }
+
+        if let Err(error) = self.save() {
+            error!("{:#}", error);
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let contents = serde_json::to_string_pretty(&self.displays)?;
+
+        fs::write(&self.path, contents)
+            .with_context(|| format!("could not save placement history {}", self.path.display()))
+    }
+}
@@ -0,0 +1,74 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+/// Oddities of the windows of an exe that yatta has to work around
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Quirks {
+    /// Only sends a name change rather than a Show event when it launches, so
+    /// every time one of its windows changes name it is handled as being shown
+    pub name_change_on_launch: bool,
+    /// Windows are managed even while DWM reports them as cloaked
+    pub allow_cloaked:         bool,
+    /// Windows are managed even though they are layered, which otherwise
+    /// filters out a lot of duplicate events
+    pub allow_layered:         bool,
+    /// Moves or resizes itself just after being shown, so new windows are put
+    /// back in their tiles again shortly after they are first tiled
+    pub resnap:                bool,
+}
+
+lazy_static! {
+    /// Looked up by the event listener thread as well as the main loop
+    static ref QUIRKS: Mutex<HashMap<String, Quirks>> = Mutex::new(builtin());
+}
+
+/// Quirks of apps that are known to need them
+pub fn builtin() -> HashMap<String, Quirks> {
+    let name_change_on_launch = Quirks {
+        name_change_on_launch: true,
+        ..Default::default()
+    };
+
+    let mut quirks = HashMap::new();
+    quirks.insert("firefox.exe".to_string(), name_change_on_launch);
+    quirks.insert("idea64.exe".to_string(), name_change_on_launch);
+    quirks.insert(
+        "steam.exe".to_string(),
+        Quirks {
+            allow_layered: true,
+            ..Default::default()
+        },
+    );
+
+    quirks
+}
+
+/// Replaces the quirks of the exes in `overrides`, keeping the built in
+/// quirks of every other exe
+pub fn set(overrides: &HashMap<String, Quirks>) {
+    let mut quirks = builtin();
+    quirks.extend(overrides.iter().map(|(exe, q)| (exe.clone(), *q)));
+
+    *QUIRKS.lock().unwrap() = quirks;
+}
+
+pub fn of(exe: &str) -> Quirks {
+    QUIRKS.lock().unwrap().get(exe).copied().unwrap_or_default()
+}
+
+/// The exes with a quirk picked out by `quirk`
+pub fn exes(quirk: fn(&Quirks) -> bool) -> Vec<String> {
+    let mut exes: Vec<String> = QUIRKS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, quirks)| quirk(quirks))
+        .map(|(exe, _)| exe.clone())
+        .collect();
+
+    exes.sort();
+    exes
+}
@@ -19,6 +19,10 @@ impl Rect {
             && point.1 <= self.y + self.height
     }
 
+    pub fn centre(self) -> (i32, i32) {
+        (self.x + (self.width / 2), self.y + (self.height / 2))
+    }
+
     pub fn zero() -> Self {
         Rect {
             x:      0,
@@ -28,6 +32,38 @@ impl Rect {
         }
     }
 
+    /// Constrains the rect to `bounds`, shrinking it where it overflows and
+    /// never producing a negative width or height
+    pub fn clamp_to(self, bounds: Rect) -> Self {
+        let right = bounds.x + bounds.width;
+        let bottom = bounds.y + bounds.height;
+
+        let x = self.x.max(bounds.x).min(right);
+        let y = self.y.max(bounds.y).min(bottom);
+
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).min(right).max(x) - x,
+            height: (self.y + self.height).min(bottom).max(y) - y,
+        }
+    }
+
+    /// Points to put the mouse cursor on to point at the rect, best first: the
+    /// centre, then the centres of its quarters. They are kept inside
+    /// `bounds` so that none of them are off the display.
+    pub fn warp_points(self, bounds: Rect) -> Vec<(i32, i32)> {
+        let visible = self.clamp_to(bounds);
+        let at = |x: i32, y: i32| {
+            (
+                visible.x + (visible.width * x / 4),
+                visible.y + (visible.height * y / 4),
+            )
+        };
+
+        vec![at(2, 2), at(1, 1), at(3, 1), at(1, 3), at(3, 3)]
+    }
+
     pub fn adjust_for_border(&mut self, border: (i32, i32)) {
         self.x -= border.0;
         self.width += border.0 * 2;
@@ -56,3 +92,14 @@ impl From<RECT> for Rect {
         }
     }
 }
+
+impl From<Rect> for yatta_core::state::Rect {
+    fn from(rect: Rect) -> Self {
+        yatta_core::state::Rect {
+            x:      rect.x,
+            y:      rect.y,
+            width:  rect.width,
+            height: rect.height,
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::hash_map::RandomState,
+    fs::{self, OpenOptions},
+    hash::{BuildHasher, Hasher},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use sysinfo::{Pid, ProcessExt, System, SystemExt};
+use uds_windows::UnixStream;
+
+use yatta_core::{MessageReader, SocketMessage, WireFormat};
+
+/// How long to wait for a yatta that might already be listening on the socket
+/// to answer
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Makes `socket` ready for this process to bind to. A lock file next to the
+/// socket holding the pid of the yatta using it keeps two from starting on the
+/// same socket, and a socket left behind by a yatta that crashed is removed
+/// once nothing answers on it. A lock file left behind by a yatta that is no
+/// longer running is taken over.
+pub fn claim(socket: &Path, system: &System) -> Result<()> {
+    lock(&lock_path(socket), system)?;
+
+    if responds(socket) {
+        bail!(
+            "another yatta is already listening on {}, stop it with yattac stop first",
+            socket.display()
+        );
+    }
+
+    match fs::remove_file(socket) {
+        Ok(_) => info!("removed stale socket {}", socket.display()),
+        // Doing this because ::exists() doesn't work reliably on Windows via IntelliJ
+        Err(error) if error.kind() == ErrorKind::NotFound => {}
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("could not remove stale socket {}", socket.display()))
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a new token to a file next to `socket` for yattac to present before
+/// yatta accepts any other messages, or removes a token left behind by a
+/// previous yatta when `required` is false. The file is created without any
+/// permissions of its own, so it inherits the ACL of the directory the socket
+/// is in; for the default socket in the home directory that only lets in the
+/// user, SYSTEM and administrators.
+pub fn token(socket: &Path, required: bool) -> Result<Option<String>> {
+    let path = sibling_path(socket, "token");
+
+    if !required {
+        return match fs::remove_file(&path) {
+            Ok(_) => Ok(None),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error)
+                .with_context(|| format!("could not remove old token file {}", path.display())),
+        };
+    }
+
+    let token = random_token();
+    fs::write(&path, &token)
+        .with_context(|| format!("could not write token file {}", path.display()))?;
+
+    info!(
+        "yattac connections must present the token in {}",
+        path.display()
+    );
+
+    Ok(Option::from(token))
+}
+
+/// Hashes nothing with a few fresh RandomStates, which std keys from the
+/// operating system's random number generator
+fn random_token() -> String {
+    (0..4)
+        .map(|_| format!("{:016x}", RandomState::new().build_hasher().finish()))
+        .collect()
+}
+
+fn lock_path(socket: &Path) -> PathBuf {
+    sibling_path(socket, "lock")
+}
+
+fn sibling_path(socket: &Path, extension: &str) -> PathBuf {
+    let mut path = socket.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+fn lock(path: &Path, system: &System) -> Result<()> {
+    // A second attempt is made after clearing out a stale lock file
+    for _ in 0..2 {
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(mut file) => {
+                return write!(file, "{}", process::id())
+                    .with_context(|| format!("could not write lock file {}", path.display()));
+            }
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                let holder = fs::read_to_string(path)
+                    .ok()
+                    .and_then(|pid| pid.trim().parse::<Pid>().ok());
+
+                if let Some(pid) = holder.filter(|pid| is_other_yatta(system, *pid)) {
+                    bail!(
+                        "yatta is already running as process {}, which holds {}",
+                        pid,
+                        path.display()
+                    );
+                }
+
+                info!("removing stale lock file {}", path.display());
+                fs::remove_file(path).with_context(|| {
+                    format!("could not remove stale lock file {}", path.display())
+                })?;
+            }
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("could not create lock file {}", path.display()))
+            }
+        }
+    }
+
+    bail!("could not lock {}", path.display())
+}
+
+/// Whether `pid` is a yatta other than this one, rather than a pid that has
+/// been reused since the yatta that had it went away
+fn is_other_yatta(system: &System, pid: Pid) -> bool {
+    pid as u32 != process::id()
+        && matches!(system.get_process(pid), Some(process) if process.name() == "yatta.exe")
+}
+
+/// Whether a yatta is listening on `socket` and answers a handshake within
+/// `PING_TIMEOUT`. The reader gives up on the read timeout, so one that accepts
+/// the connection and then hangs doesn't hold up startup.
+fn responds(socket: &Path) -> bool {
+    let stream = match UnixStream::connect(socket) {
+        Ok(stream) => stream,
+        Err(_) => return false,
+    };
+
+    if stream.set_read_timeout(Option::from(PING_TIMEOUT)).is_err() {
+        return false;
+    }
+
+    let ping = match WireFormat::Json.encode(&SocketMessage::Handshake(WireFormat::Json)) {
+        Ok(ping) => ping,
+        Err(_) => return false,
+    };
+
+    let mut connection = MessageReader::new(stream);
+    connection.get_mut().write_all(&ping).is_ok() && matches!(connection.read_frame(), Ok(Some(_)))
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use yatta_core::{
+    state::{DisplayState, ManagedGroup, State, WindowState, STATE_VERSION},
+    GroupBy,
+    Layout,
+};
+
+use crate::{
+    desktop::{Desktop, Display},
+    window::Window,
+};
+
+impl From<&Desktop> for State {
+    fn from(desktop: &Desktop) -> Self {
+        State {
+            version:  STATE_VERSION,
+            paused:   desktop.paused,
+            displays: displays(desktop),
+        }
+    }
+}
+
+/// The state of every display, in display number order
+pub fn displays(desktop: &Desktop) -> Vec<DisplayState> {
+    desktop
+        .displays
+        .iter()
+        .enumerate()
+        .map(|(idx, display)| DisplayState {
+            area:      display.get_dimensions().into(),
+            layout:    display.layout,
+            monocle:   matches!(display.layout, Layout::Monocle),
+            inner_gap: display.inner_gap,
+            outer_gap: display.outer_gap,
+            windows:   display
+                .windows
+                .iter()
+                .map(|window| window_state(window, display, idx + 1))
+                .collect(),
+        })
+        .collect()
+}
+
+/// Every managed window, in display number order
+pub fn windows(desktop: &Desktop) -> Vec<WindowState> {
+    displays(desktop)
+        .into_iter()
+        .flat_map(|display| display.windows)
+        .collect()
+}
+
+/// The focused window of the active display, if yatta is managing it
+pub fn focused(desktop: &Desktop) -> Option<WindowState> {
+    let idx = desktop.get_active_display_idx();
+    let display = desktop.displays.get(idx)?;
+
+    display
+        .windows
+        .iter()
+        .find(|window| window.hwnd == display.foreground_window.hwnd)
+        .map(|window| window_state(window, display, idx + 1))
+}
+
+fn window_state(window: &Window, display: &Display, number: usize) -> WindowState {
+    WindowState {
+        hwnd:      window.hwnd.0 as u64,
+        title:     window.title(),
+        exe:       window.exe_name(),
+        rect:      window.rect().into(),
+        floating:  !window.should_tile(),
+        focused:   window.hwnd == display.foreground_window.hwnd,
+        unmovable: window.is_unmovable(),
+        hidden:    window.hidden,
+        display:   number,
+    }
+}
+
+/// Counts the managed windows on every display by exe or class, most common
+/// first
+pub fn managed(desktop: &Desktop, group_by: GroupBy) -> Vec<ManagedGroup> {
+    let mut groups: HashMap<String, ManagedGroup> = HashMap::new();
+
+    for window in desktop.displays.iter().flat_map(|display| &display.windows) {
+        let name = match group_by {
+            GroupBy::Exe => window.exe_name(),
+            GroupBy::Class => window.class().unwrap_or_default(),
+        };
+
+        let group = groups.entry(name.clone()).or_insert(ManagedGroup {
+            name,
+            windows: 0,
+            floating: 0,
+        });
+
+        group.windows += 1;
+        if !window.should_tile() {
+            group.floating += 1;
+        }
+    }
+
+    let mut groups: Vec<ManagedGroup> = groups.into_values().collect();
+    groups.sort_by(|x, y| y.windows.cmp(&x.windows).then_with(|| x.name.cmp(&y.name)));
+
+    groups
+}
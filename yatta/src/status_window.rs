@@ -0,0 +1,245 @@
+use std::{ptr, thread};
+
+use anyhow::{bail, Result};
+use crossbeam_channel::bounded;
+
+use bindings::Windows::Win32::{
+    Foundation::{HWND, LPARAM, LRESULT, PWSTR, WPARAM},
+    System::{
+        Com::{CoCreateInstance, CoInitializeEx, CLSCTX_INPROC_SERVER, COINIT_APARTMENTTHREADED},
+        LibraryLoader::GetModuleHandleW,
+        OleAutomation::{VARIANT, VT_I4},
+    },
+    UI::{
+        Accessibility::{
+            CAccPropServices,
+            IAccPropServices,
+            LiveSetting_Property_GUID,
+            NotifyWinEvent,
+        },
+        WindowsAndMessaging::{
+            CreateWindowExW,
+            DefWindowProcW,
+            RegisterClassW,
+            SetWindowTextW,
+            CHILDID_SELF,
+            EVENT_OBJECT_LIVEREGIONCHANGED,
+            HMENU,
+            OBJID_CLIENT,
+            WM_DISPLAYCHANGE,
+            WNDCLASSW,
+            WS_EX_TOOLWINDOW,
+            WS_OVERLAPPED,
+        },
+    },
+};
+use log::error;
+
+use crate::{desktop::Desktop, message_loop, Message, YATTA_CHANNEL};
+
+const CLASS_NAME: &str = "yatta_status";
+
+/// The Polite value of the UIA LiveSetting property, which has screen readers
+/// wait for a pause in what they are already reading out
+const LIVE_SETTING_POLITE: i32 = 1;
+
+/// A window that is never shown, whose title describes the active display so
+/// that tools which can only read window titles can follow along with yatta.
+/// It can be found by its class name, `yatta_status`. Being a top level
+/// window, it is also what hears about displays changing.
+///
+/// When `announce` is set, the title spells out the state of the active
+/// display in full and the window is made a UIA live region, so screen readers
+/// read out every change of display, layout or focus.
+pub struct StatusWindow {
+    hwnd:     HWND,
+    title:    String,
+    announce: bool,
+}
+
+impl StatusWindow {
+    pub fn create(announce: bool) -> Result<Self> {
+        let (sender, receiver) = bounded(1);
+
+        // The window has to be created on a thread that keeps pumping messages,
+        // otherwise setting its title from the main loop would block
+        thread::spawn(move || unsafe {
+            let instance = GetModuleHandleW(PWSTR::default());
+            let mut class_name = to_wide(CLASS_NAME);
+            let mut title = to_wide("yatta");
+
+            let class = WNDCLASSW {
+                lpfnWndProc: Some(window_proc),
+                hInstance: instance,
+                lpszClassName: PWSTR(class_name.as_mut_ptr()),
+                ..Default::default()
+            };
+
+            RegisterClassW(&class);
+
+            let hwnd = CreateWindowExW(
+                WS_EX_TOOLWINDOW,
+                PWSTR(class_name.as_mut_ptr()),
+                PWSTR(title.as_mut_ptr()),
+                WS_OVERLAPPED,
+                0,
+                0,
+                0,
+                0,
+                HWND(0),
+                HMENU(0),
+                instance,
+                ptr::null_mut(),
+            );
+
+            if hwnd.0 != 0 && announce {
+                if let Err(error) = make_live_region(hwnd) {
+                    error!("could not make the status window a live region: {}", error);
+                }
+            }
+
+            let _ = sender.send(hwnd);
+
+            if hwnd.0 != 0 {
+                message_loop::start(|_| true);
+            }
+        });
+
+        let hwnd = receiver.recv()?;
+        if hwnd.0 == 0 {
+            bail!("could not create status window");
+        }
+
+        Ok(StatusWindow {
+            hwnd,
+            title: String::new(),
+            announce,
+        })
+    }
+
+    pub fn update(&mut self, desktop: &Desktop) {
+        let title = if self.announce {
+            announcement(desktop)
+        } else {
+            status(desktop)
+        };
+
+        if title == self.title {
+            return;
+        }
+
+        let mut wide = to_wide(&title);
+        unsafe {
+            SetWindowTextW(self.hwnd, PWSTR(wide.as_mut_ptr()));
+
+            if self.announce {
+                NotifyWinEvent(
+                    EVENT_OBJECT_LIVEREGIONCHANGED as u32,
+                    self.hwnd,
+                    OBJID_CLIENT,
+                    CHILDID_SELF,
+                );
+            }
+        }
+
+        self.title = title;
+    }
+}
+
+/// For example "yatta | display 2 | bspv", with "| paused" at the end while
+/// tiling is paused
+fn status(desktop: &Desktop) -> String {
+    let idx = desktop.get_active_display_idx();
+
+    let mut status = match desktop.displays.get(idx) {
+        Some(display) => format!("yatta | display {} | {}", idx + 1, display.layout),
+        None => String::from("yatta"),
+    };
+
+    if desktop.paused {
+        status.push_str(" | paused");
+    }
+
+    status
+}
+
+/// For example "yatta, display 2 of 3, bspv layout, 4 windows, focused
+/// Inbox - Mozilla Thunderbird", with ", paused" at the end while tiling is
+/// paused
+fn announcement(desktop: &Desktop) -> String {
+    let idx = desktop.get_active_display_idx();
+
+    let mut announcement = match desktop.displays.get(idx) {
+        Some(display) => {
+            let mut announcement = format!(
+                "yatta, display {} of {}, {} layout, {} {}",
+                idx + 1,
+                desktop.displays.len(),
+                display.layout,
+                display.windows.len(),
+                if display.windows.len() == 1 {
+                    "window"
+                } else {
+                    "windows"
+                }
+            );
+
+            if let Some(title) = display.foreground_window.title() {
+                announcement.push_str(&format!(", focused {}", title));
+            }
+
+            announcement
+        }
+        None => String::from("yatta"),
+    };
+
+    if desktop.paused {
+        announcement.push_str(", paused");
+    }
+
+    announcement
+}
+
+/// Sets the LiveSetting property of the window so that screen readers pick up
+/// the name changes that are signalled with EVENT_OBJECT_LIVEREGIONCHANGED
+unsafe fn make_live_region(hwnd: HWND) -> Result<()> {
+    CoInitializeEx(ptr::null_mut(), COINIT_APARTMENTTHREADED)?;
+
+    let services: IAccPropServices =
+        CoCreateInstance(&CAccPropServices, None, CLSCTX_INPROC_SERVER)?;
+
+    let mut polite = VARIANT::default();
+    polite.Anonymous.Anonymous.vt = VT_I4.0 as u16;
+    polite.Anonymous.Anonymous.Anonymous.lVal = LIVE_SETTING_POLITE;
+
+    services
+        .SetHwndProp(
+            hwnd,
+            OBJID_CLIENT as u32,
+            CHILDID_SELF as u32,
+            LiveSetting_Property_GUID,
+            polite,
+        )
+        .ok()?;
+
+    Ok(())
+}
+
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    if msg == WM_DISPLAYCHANGE {
+        if let Err(error) = YATTA_CHANNEL.lock().unwrap().0.send(Message::DisplayChange) {
+            error!("could not send display change to yatta channel: {}", error);
+        }
+    }
+
+    DefWindowProcW(hwnd, msg, wparam, lparam)
+}
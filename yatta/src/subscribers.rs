@@ -0,0 +1,188 @@
+use crossbeam_channel::Sender;
+use log::{error, info};
+
+use bindings::Windows::Win32::Foundation::HWND;
+use yatta_core::{state::Event, Layout};
+
+use crate::{desktop::Desktop, window::Window};
+
+/// The `yattac subscribe` connections, which are sent an `Event` for every
+/// change to the desktop since the last time it was looked at. The desktop is
+/// looked at after every message the main loop handles, so changes that
+/// don't come with a message, like the cursor moving to another display, are
+/// only sent along with the next one.
+#[derive(Default)]
+pub struct Subscribers {
+    senders: Vec<Sender<String>>,
+    last:    Snapshot,
+}
+
+impl Subscribers {
+    pub fn add(&mut self, sender: Sender<String>, desktop: &Desktop) {
+        // Nothing has been keeping track while there was no one to tell
+        if self.senders.is_empty() {
+            self.last = Snapshot::from(desktop);
+        }
+
+        self.senders.push(sender);
+    }
+
+    /// Sends what has changed since the last call to every subscriber,
+    /// forgetting about subscribers that have gone away
+    pub fn publish(&mut self, desktop: &Desktop) {
+        if self.senders.is_empty() {
+            return;
+        }
+
+        let current = Snapshot::from(desktop);
+        if current == self.last {
+            return;
+        }
+
+        for event in self.last.changes(&current) {
+            let event = match serde_json::to_string(&event) {
+                Ok(event) => event,
+                Err(error) => {
+                    error!(
+                        "could not serialize event for yattac subscribers: {}",
+                        error
+                    );
+                    continue;
+                }
+            };
+
+            let subscribers = self.senders.len();
+            self.senders
+                .retain(|sender| sender.send(event.clone()).is_ok());
+
+            if self.senders.len() < subscribers {
+                info!(
+                    "{} yattac subscriber(s) went away",
+                    subscribers - self.senders.len()
+                );
+            }
+        }
+
+        self.last = current;
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Snapshot {
+    paused:   bool,
+    active:   usize,
+    displays: Vec<DisplaySnapshot>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct DisplaySnapshot {
+    layout:  Layout,
+    focused: HWND,
+    windows: Vec<HWND>,
+}
+
+impl From<&Desktop> for Snapshot {
+    fn from(desktop: &Desktop) -> Self {
+        Snapshot {
+            paused:   desktop.paused,
+            active:   desktop.get_active_display_idx(),
+            displays: desktop
+                .displays
+                .iter()
+                .map(|display| DisplaySnapshot {
+                    layout:  display.layout,
+                    focused: display.foreground_window.hwnd,
+                    windows: display.windows.iter().map(|window| window.hwnd).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Snapshot {
+    /// The events that turn this snapshot into `current`
+    fn changes(&self, current: &Snapshot) -> Vec<Event> {
+        let mut events = vec![];
+
+        if current.paused != self.paused {
+            events.push(Event::Paused {
+                paused: current.paused,
+            });
+        }
+
+        for (idx, display) in current.displays.iter().enumerate() {
+            let number = idx + 1;
+            let previous = self.displays.get(idx);
+
+            for hwnd in previous.iter().flat_map(|previous| &previous.windows) {
+                if !display.windows.contains(hwnd) {
+                    events.push(Event::WindowUnmanaged {
+                        display: number,
+                        hwnd:    hwnd.0 as u64,
+                    });
+                }
+            }
+
+            for hwnd in &display.windows {
+                if !matches!(previous, Some(previous) if previous.windows.contains(hwnd)) {
+                    let window = window(*hwnd);
+                    events.push(Event::WindowManaged {
+                        display: number,
+                        hwnd:    hwnd.0 as u64,
+                        title:   window.title(),
+                        exe:     window.exe_name(),
+                    });
+                }
+            }
+
+            if !matches!(previous, Some(previous) if previous.layout == display.layout) {
+                events.push(Event::LayoutChanged {
+                    display: number,
+                    layout:  display.layout,
+                });
+            }
+
+            if display.focused.0 != 0
+                && !matches!(previous, Some(previous) if previous.focused == display.focused)
+            {
+                events.push(Event::WindowFocused {
+                    display: number,
+                    hwnd:    display.focused.0 as u64,
+                    title:   window(display.focused).title(),
+                });
+            }
+        }
+
+        // Windows on displays that have been disconnected are managed again on
+        // whichever display they end up on
+        for (idx, display) in self
+            .displays
+            .iter()
+            .enumerate()
+            .skip(current.displays.len())
+        {
+            for hwnd in &display.windows {
+                events.push(Event::WindowUnmanaged {
+                    display: idx + 1,
+                    hwnd:    hwnd.0 as u64,
+                });
+            }
+        }
+
+        if current.active != self.active {
+            events.push(Event::DisplayFocused {
+                display: current.active + 1,
+            });
+        }
+
+        events
+    }
+}
+
+/// Enough of a window to look up its title and exe
+fn window(hwnd: HWND) -> Window {
+    Window {
+        hwnd,
+        ..Default::default()
+    }
+}
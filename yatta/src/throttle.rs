@@ -0,0 +1,112 @@
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use yatta_core::SocketMessage;
+
+/// How many commands a yattac connection can send at once before it is held
+/// to `COMMANDS_PER_SECOND`
+const COMMAND_BURST: f64 = 50.0;
+const COMMANDS_PER_SECOND: f64 = 50.0;
+
+/// An identical idempotent command that arrives within this time of the last
+/// one being handled is dropped
+const COALESCE_WINDOW: Duration = Duration::from_millis(100);
+
+/// Keeps a single yattac connection, like a script stuck in a loop, from
+/// flooding yatta with more commands than it can handle
+pub struct RateLimit {
+    allowance: f64,
+    refilled:  Instant,
+    dropped:   usize,
+}
+
+impl RateLimit {
+    pub fn new() -> Self {
+        RateLimit {
+            allowance: COMMAND_BURST,
+            refilled:  Instant::now(),
+            dropped:   0,
+        }
+    }
+
+    /// Whether a command that arrived `now` should be handled rather than
+    /// dropped
+    pub fn allow(&mut self, now: Instant) -> bool {
+        let refill = now.duration_since(self.refilled).as_secs_f64() * COMMANDS_PER_SECOND;
+        self.allowance = (self.allowance + refill).min(COMMAND_BURST);
+        self.refilled = now;
+
+        if self.allowance < 1.0 {
+            self.dropped += 1;
+            if self.dropped == 1 {
+                warn!(
+                    "dropping commands from a yattac connection sending more than {} a second",
+                    COMMANDS_PER_SECOND
+                );
+            }
+
+            return false;
+        }
+
+        if self.dropped > 0 {
+            warn!("dropped {} commands from a yattac connection", self.dropped);
+            self.dropped = 0;
+        }
+
+        self.allowance -= 1.0;
+        true
+    }
+}
+
+/// Drops commands that repeat the one handled just before them without
+/// changing anything, across every yattac connection
+#[derive(Default)]
+pub struct Coalesce {
+    last:    Option<(String, Instant)>,
+    dropped: usize,
+}
+
+impl Coalesce {
+    /// Whether `msg`, arriving `now`, should be handled rather than dropped
+    pub fn allow(&mut self, msg: &SocketMessage, now: Instant) -> bool {
+        let key = format!("{:?}", msg);
+        let repeated = match &self.last {
+            Some((last, handled)) => *last == key && now.duration_since(*handled) < COALESCE_WINDOW,
+            None => false,
+        };
+
+        if repeated {
+            self.dropped += 1;
+            if self.dropped == 1 {
+                warn!(
+                    "dropping {} commands identical to the one just handled",
+                    msg
+                );
+            }
+
+            return false;
+        }
+
+        if self.dropped > 0 {
+            warn!("dropped {} repeated commands", self.dropped);
+            self.dropped = 0;
+        }
+
+        self.last = if msg.is_idempotent() {
+            Option::from((key, now))
+        } else {
+            None
+        };
+
+        true
+    }
+
+    /// Restarts the window for dropping repeats from when the last command
+    /// finished being handled
+    pub fn handled(&mut self, now: Instant) {
+        if let Some((_, handled)) = &mut self.last {
+            *handled = now;
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::{collections::HashSet, ptr, thread, time::Duration};
+
+use anyhow::Result;
+use crossbeam_channel::Sender;
+use log::{error, info};
+
+use bindings::Windows::Win32::{
+    Foundation::HWND,
+    Graphics::Gdi::{MonitorFromWindow, MONITOR_DEFAULTTOPRIMARY},
+    System::Com::{CoCreateInstance, CoInitializeEx, CLSCTX_INPROC_SERVER, COINIT_MULTITHREADED},
+    UI::Accessibility::{
+        CUIAutomation,
+        IUIAutomation,
+        IUIAutomationCondition,
+        IUIAutomationElement,
+        IUIAutomationElementArray,
+        TreeScope_Children,
+    },
+};
+
+use crate::{
+    window::Window,
+    windows_event::{WinEventCode, WindowsEvent, WindowsEventType},
+    Message,
+    YATTA_CHANNEL,
+};
+
+/// Starts a thread that asks UI Automation for the top level windows every
+/// `poll_ms` milliseconds and sends a Show for every new window that should be
+/// managed, and a Hide or Destroy once one of them is gone again. It catches
+/// windows that open or close without sending any WinEvents, at the cost of
+/// walking the UIA tree over and over, so it is only started when
+/// uia_discovery_poll_ms is set. Any window it reports twice is ignored by the
+/// main loop as usual.
+pub fn start(poll_ms: u64) {
+    let yatta_sender = YATTA_CHANNEL.lock().unwrap().0.clone();
+
+    thread::spawn(move || unsafe {
+        let (automation, condition) = match connect() {
+            Ok(connection) => connection,
+            Err(error) => {
+                error!("could not start ui automation discovery: {}", error);
+                return;
+            }
+        };
+
+        info!("starting ui automation discovery");
+
+        // The first pass only records what is already open, the initial tiling
+        // has taken care of those windows
+        let mut known: Option<HashSet<isize>> = None;
+
+        loop {
+            let current = match top_level_windows(&automation, &condition) {
+                Ok(current) => current,
+                Err(error) => {
+                    error!("could not list windows with ui automation: {}", error);
+                    thread::sleep(Duration::from_millis(poll_ms));
+                    continue;
+                }
+            };
+
+            let mut managed = HashSet::new();
+            for hwnd in current {
+                let window = window(hwnd);
+                let was_known = matches!(&known, Some(known) if known.contains(&hwnd));
+
+                if was_known || window.should_manage(Option::from(WindowsEventType::Show)) {
+                    managed.insert(hwnd);
+                }
+
+                if !was_known && known.is_some() && managed.contains(&hwnd) {
+                    send(&yatta_sender, window, WindowsEventType::Show);
+                }
+            }
+
+            for hwnd in known
+                .iter()
+                .flatten()
+                .filter(|hwnd| !managed.contains(hwnd))
+            {
+                let window = window(*hwnd);
+                let event_type = if window.is_window() {
+                    WindowsEventType::Hide
+                } else {
+                    WindowsEventType::Destroy
+                };
+
+                send(&yatta_sender, window, event_type);
+            }
+
+            known = Option::from(managed);
+            thread::sleep(Duration::from_millis(poll_ms));
+        }
+    });
+}
+
+unsafe fn connect() -> Result<(IUIAutomation, IUIAutomationCondition)> {
+    CoInitializeEx(ptr::null_mut(), COINIT_MULTITHREADED)?;
+
+    let automation: IUIAutomation = CoCreateInstance(&CUIAutomation, None, CLSCTX_INPROC_SERVER)?;
+
+    let mut condition = None;
+    let condition = automation
+        .CreateTrueCondition(&mut condition)
+        .and_some(condition)?;
+
+    Ok((automation, condition))
+}
+
+/// The handles of the children of the desktop element, which are what UI
+/// Automation considers top level windows
+unsafe fn top_level_windows(
+    automation: &IUIAutomation,
+    condition: &IUIAutomationCondition,
+) -> Result<Vec<isize>> {
+    let mut root = None;
+    let root: IUIAutomationElement = automation.GetRootElement(&mut root).and_some(root)?;
+
+    let mut found = None;
+    let found: IUIAutomationElementArray = root
+        .FindAll(TreeScope_Children, condition, &mut found)
+        .and_some(found)?;
+
+    let mut length = 0;
+    found.Length(&mut length).ok()?;
+
+    let mut hwnds = vec![];
+    for i in 0..length {
+        let mut element = None;
+        let element = found.GetElement(i, &mut element).and_some(element)?;
+
+        let mut hwnd = HWND::default();
+        element.CurrentNativeWindowHandle(&mut hwnd).ok()?;
+
+        if hwnd.0 != 0 {
+            hwnds.push(hwnd.0);
+        }
+    }
+
+    Ok(hwnds)
+}
+
+fn window(hwnd: isize) -> Window {
+    let hwnd = HWND(hwnd);
+
+    Window {
+        hwnd,
+        hmonitor: unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTOPRIMARY) },
+        ..Default::default()
+    }
+}
+
+fn send(yatta_sender: &Sender<Message>, window: Window, event_type: WindowsEventType) {
+    let event_code = match event_type {
+        WindowsEventType::Show => WinEventCode::ObjectShow,
+        WindowsEventType::Hide => WinEventCode::ObjectHide,
+        _ => WinEventCode::ObjectDestroy,
+    };
+
+    let event = WindowsEvent {
+        event_type,
+        event_code,
+        window,
+        title: window.title(),
+    };
+
+    if let Err(error) = yatta_sender.send(Message::WindowsEvent(event)) {
+        error!(
+            "could not send ui automation event to yatta channel: {}",
+            error
+        );
+    }
+}
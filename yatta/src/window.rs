@@ -1,45 +1,66 @@
 use std::mem;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use bitflags::bitflags;
-use log::debug;
+use log::{debug, error};
+use regex::{Regex, RegexBuilder};
 
 use bindings::Windows::Win32::{
-    Foundation::{HWND, PWSTR},
+    Foundation::{CloseHandle, HANDLE, HWND, LPARAM, POINT, PWSTR, RECT, WPARAM},
     Graphics::{
-        Dwm::{DwmGetWindowAttribute, DWMWA_CLOAKED},
+        Dwm::{
+            DwmGetWindowAttribute,
+            DwmSetWindowAttribute,
+            DWMWA_CLOAKED,
+            DWMWA_EXTENDED_FRAME_BOUNDS,
+        },
         Gdi::{MonitorFromWindow, HMONITOR, MONITOR_DEFAULTTOPRIMARY},
     },
     System::Threading::{
         OpenProcess,
         QueryFullProcessImageNameW,
+        PROCESS_ACCESS_RIGHTS,
         PROCESS_NAME_FORMAT,
         PROCESS_QUERY_INFORMATION,
+        PROCESS_QUERY_LIMITED_INFORMATION,
     },
     UI::{
-        KeyboardAndMouseInput::SetFocus,
+        KeyboardAndMouseInput::{IsWindowEnabled, SetFocus},
         WindowsAndMessaging::{
+            GetAncestor,
+            GetCursorPos,
             GetForegroundWindow,
+            GetShellWindow,
+            GetWindow,
             GetWindowInfo,
             GetWindowLongW,
             GetWindowRect,
-            GetWindowTextW,
             GetWindowThreadProcessId,
             IsIconic,
-            IsWindow,
-            IsWindowVisible,
             RealGetWindowClassW,
+            SendMessageTimeoutW,
             SetCursorPos,
             SetForegroundWindow,
             SetWindowPos,
             ShowWindow,
+            WindowFromPoint,
+            GA_ROOT,
             GWL_EXSTYLE,
             GWL_STYLE,
-            HWND_BOTTOM,
+            GW_OWNER,
             SET_WINDOW_POS_FLAGS,
+            SMTO_ABORTIFHUNG,
+            SWP_ASYNCWINDOWPOS,
             SWP_NOACTIVATE,
+            SWP_NOMOVE,
+            SWP_NOSIZE,
+            SWP_NOZORDER,
+            SW_HIDE,
             SW_RESTORE,
+            SW_SHOWMINNOACTIVE,
+            SW_SHOWNOACTIVATE,
             WINDOWINFO,
+            WM_GETTEXT,
             WS_BORDER,
             WS_CAPTION,
             WS_CHILD,
@@ -98,14 +119,13 @@ use bindings::Windows::Win32::{
     },
 };
 
-use crate::{
-    rect::Rect,
-    windows_event::WindowsEventType,
-    FLOAT_CLASSES,
-    FLOAT_EXES,
-    FLOAT_TITLES,
-    LAYERED_EXE_WHITELIST,
-};
+// Tests ask the mock below instead, see `mock`
+#[cfg(not(test))]
+use bindings::Windows::Win32::UI::WindowsAndMessaging::{IsWindow, IsWindowVisible};
+
+use yatta_core::{Rules, WindowRule};
+
+use crate::{quirks, rect::Rect, windows_event::WindowsEventType};
 
 bitflags! {
     #[derive(Default)]
@@ -175,10 +195,17 @@ bitflags! {
 
 #[derive(Clone, Copy, Debug)]
 pub struct Window {
-    pub hwnd:     HWND,
-    pub hmonitor: HMONITOR,
-    pub tile:     bool,
-    pub resize:   Option<Rect>,
+    pub hwnd:         HWND,
+    pub hmonitor:     HMONITOR,
+    pub tile:         bool,
+    /// Matches one of the float rules, worked out when the window is managed
+    /// and again whenever the rules change
+    pub float_rule:   bool,
+    pub resize:       Option<Rect>,
+    /// Hidden or minimized by yatta rather than by the user
+    pub hidden:       bool,
+    /// Layout passes in a row in which the window couldn't be moved
+    pub pos_failures: u8,
 }
 
 unsafe impl Send for Window {}
@@ -192,7 +219,115 @@ fn nullable_to_result<T: PartialEq<i32>>(v: T) -> Result<T> {
 }
 
 pub fn exe_name_from_path(path: &str) -> String {
-    path.split('\\').last().unwrap().to_string()
+    path.rsplit('\\').next().unwrap_or(path).to_string()
+}
+
+/// A float_windows rule with its title pattern compiled, so that it is only
+/// compiled and checked once, when the rule is loaded
+#[derive(Clone, Debug)]
+pub struct FloatWindowRule {
+    rule:  WindowRule,
+    title: Option<Regex>,
+}
+
+impl FloatWindowRule {
+    pub fn new(rule: WindowRule) -> Result<Self> {
+        let title = match &rule.title {
+            Some(pattern) => Option::from(
+                RegexBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .with_context(|| {
+                        format!("invalid title pattern '{}' in float_windows", pattern)
+                    })?,
+            ),
+            None => None,
+        };
+
+        Ok(FloatWindowRule { rule, title })
+    }
+
+    pub fn rule(&self) -> &WindowRule {
+        &self.rule
+    }
+
+    /// Whether a window with these properties has every property that the
+    /// rule asks for. A rule without any properties matches nothing.
+    pub fn matches(&self, class: Option<&str>, exe: Option<&str>, title: Option<&str>) -> bool {
+        if self.rule == WindowRule::default() {
+            return false;
+        }
+
+        let class_matches = match &self.rule.class {
+            Some(expected) => class == Some(expected.as_str()),
+            None => true,
+        };
+
+        let exe_matches = match &self.rule.exe {
+            Some(expected) => matches!(exe, Some(exe) if exe.eq_ignore_ascii_case(expected)),
+            None => true,
+        };
+
+        let title_matches = match (&self.title, title) {
+            (Some(regex), Some(title)) => regex.is_match(title),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+
+        class_matches && exe_matches && title_matches
+    }
+}
+
+/// Compiles float_windows rules, leaving out the ones that are invalid
+pub fn compile_float_windows(rules: Vec<WindowRule>) -> Vec<FloatWindowRule> {
+    rules
+        .into_iter()
+        .filter_map(|rule| match FloatWindowRule::new(rule) {
+            Ok(rule) => Option::from(rule),
+            Err(error) => {
+                error!("ignoring float_windows rule: {:#}", error);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Window class used by standard dialog boxes such as message boxes and file
+/// pickers
+const DIALOG_CLASS: &str = "#32770";
+
+/// Windows that can't be moved in this many layout passes in a row are floated,
+/// which usually means they belong to an elevated or hung process
+const POS_FAILURE_LIMIT: u8 = 3;
+
+/// How long to wait on a window that is busy but not hung before giving up on
+/// a message sent to it
+const MESSAGE_TIMEOUT_MS: u32 = 100;
+
+/// Placeholder used in logs when the exe of a window cannot be resolved
+const UNKNOWN_EXE: &str = "<unknown exe>";
+
+/// Owned process handle which is closed when dropped
+struct ProcessHandle(HANDLE);
+
+impl ProcessHandle {
+    fn open(pid: u32, access: PROCESS_ACCESS_RIGHTS) -> Result<Self> {
+        let handle = unsafe { OpenProcess(access, false, pid) };
+
+        if handle.0 == 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(Self(handle))
+    }
+}
+
+impl Drop for ProcessHandle {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.0);
+        }
+    }
 }
 
 impl Window {
@@ -203,44 +338,57 @@ impl Window {
         Window {
             hwnd,
             hmonitor,
-            tile: true,
-            resize: None,
+            ..Default::default()
+        }
+    }
+
+    /// The desktop (Progman) window owned by the shell
+    pub fn shell() -> Window {
+        let hwnd = unsafe { GetShellWindow() };
+        let hmonitor = unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTOPRIMARY) };
+
+        Window {
+            hwnd,
+            hmonitor,
+            ..Default::default()
         }
     }
 
     pub fn should_tile(&self) -> bool {
-        let classes = FLOAT_CLASSES.lock().unwrap();
-        let exes = FLOAT_EXES.lock().unwrap();
-        let titles = FLOAT_TITLES.lock().unwrap();
+        self.tile && !self.float_rule && !self.is_unmovable()
+    }
 
-        let mut should = true;
+    /// Whether yatta has given up on moving the window
+    pub fn is_unmovable(&self) -> bool {
+        self.pos_failures >= POS_FAILURE_LIMIT
+    }
 
-        if !self.tile {
-            should = false
-        }
+    pub fn matches_float_rule(&self, rules: &Rules, float_windows: &[FloatWindowRule]) -> bool {
+        let class = self.class().ok();
+        let exe = self.exe_path().ok().map(|path| exe_name_from_path(&path));
+        let title = self.title();
 
-        if let Ok(class) = self.class() {
-            if classes.contains(&class) {
-                should = false
+        if let Some(class) = &class {
+            if rules.float_classes.contains(class) {
+                return true;
             }
         }
 
-        if let Ok(exe_path) = self.exe_path() {
-            let exe = exe_name_from_path(&exe_path);
-            if exes.contains(&exe) {
-                should = false
+        if let Some(exe) = &exe {
+            if rules.float_exes.contains(exe) {
+                return true;
             }
         }
 
-        if let Some(title) = self.title() {
-            for t in titles.iter() {
-                if title.contains(t) {
-                    should = false
-                }
+        if let Some(title) = &title {
+            if rules.float_titles.iter().any(|t| title.contains(t)) {
+                return true;
             }
         }
 
-        should
+        float_windows
+            .iter()
+            .any(|rule| rule.matches(class.as_deref(), exe.as_deref(), title.as_deref()))
     }
 
     pub fn class(&self) -> Result<String> {
@@ -266,30 +414,54 @@ impl Window {
 
     pub fn exe_path(&self) -> Result<String> {
         let (pid, _) = self.thread_process_id();
-        // PROCESS_QUERY_INFORMATION (0x0400)
+        if pid == 0 {
+            return Err(anyhow::anyhow!(
+                "could not look up process id for hwnd {}",
+                self.hwnd.0
+            ));
+        }
+
+        // PROCESS_QUERY_INFORMATION (0x0400) is denied for elevated processes when we
+        // are not elevated ourselves, but PROCESS_QUERY_LIMITED_INFORMATION
+        // (0x1000) is still enough for QueryFullProcessImageNameW
         // https://docs.microsoft.com/en-us/windows/win32/procthread/process-security-and-access-rights
-        let handle = unsafe { OpenProcess(PROCESS_QUERY_INFORMATION, false, pid) };
+        let handle = ProcessHandle::open(pid, PROCESS_QUERY_INFORMATION)
+            .or_else(|_| ProcessHandle::open(pid, PROCESS_QUERY_LIMITED_INFORMATION))
+            .with_context(|| format!("could not open process {}", pid))?;
 
-        let mut buf_len = 260_u32;
+        // Paths can be longer than MAX_PATH (260) if long path support is enabled
+        let mut buf_len = 1024_u32;
         let mut result: Vec<u16> = vec![0; buf_len as usize];
         let text_ptr = result.as_mut_ptr();
 
-        unsafe {
-            let success: bool = QueryFullProcessImageNameW(
-                handle,
+        let success: bool = unsafe {
+            QueryFullProcessImageNameW(
+                handle.0,
                 PROCESS_NAME_FORMAT(0),
                 PWSTR(text_ptr),
                 &mut buf_len as *mut u32,
             )
-            .into();
-            if !success {
-                return Err(std::io::Error::last_os_error().into());
-            }
+            .into()
+        };
+
+        if !success {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("could not query image name of process {}", pid));
         }
 
         Ok(String::from_utf16_lossy(&result[..buf_len as usize]))
     }
 
+    pub fn exe_name(&self) -> String {
+        match self.exe_path() {
+            Ok(path) => exe_name_from_path(&path),
+            Err(error) => {
+                debug!("{:#}", error);
+                UNKNOWN_EXE.to_string()
+            }
+        }
+    }
+
     pub fn rect(self) -> Rect {
         unsafe {
             let mut rect = mem::zeroed();
@@ -300,8 +472,50 @@ impl Window {
         }
     }
 
+    /// The part of the window that can be seen, without the drop shadow and
+    /// invisible resize borders that `rect` includes. Falls back to `rect` if
+    /// DWM isn't drawing the window.
+    pub fn frame_rect(self) -> Rect {
+        unsafe {
+            let mut rect: RECT = mem::zeroed();
+
+            let found = DwmGetWindowAttribute(
+                self.hwnd,
+                DWMWA_EXTENDED_FRAME_BOUNDS.0 as u32,
+                &mut rect as *mut _ as *mut _,
+                std::mem::size_of::<RECT>() as u32,
+            );
+
+            if found.is_ok() {
+                rect.into()
+            } else {
+                self.rect()
+            }
+        }
+    }
+
+    /// Where to put the window so that the part of it that can be seen fills
+    /// `rect` exactly
+    pub fn compensate_frame(self, rect: Rect) -> Rect {
+        let window = self.rect();
+        let frame = self.frame_rect();
+
+        Rect {
+            x:      rect.x - (frame.x - window.x),
+            y:      rect.y - (frame.y - window.y),
+            width:  rect.width + (window.width - frame.width),
+            height: rect.height + (window.height - frame.height),
+        }
+    }
+
     pub fn is_visible(self) -> bool {
-        unsafe { IsWindowVisible(self.hwnd).into() }
+        #[cfg(test)]
+        return mock::is_open(self.hwnd);
+
+        #[cfg(not(test))]
+        unsafe {
+            IsWindowVisible(self.hwnd).into()
+        }
     }
 
     pub fn is_minimized(self) -> bool {
@@ -309,7 +523,63 @@ impl Window {
     }
 
     pub fn is_window(self) -> bool {
-        unsafe { IsWindow(self.hwnd).into() }
+        #[cfg(test)]
+        return mock::is_open(self.hwnd);
+
+        #[cfg(not(test))]
+        unsafe {
+            IsWindow(self.hwnd).into()
+        }
+    }
+
+    /// Dialog boxes, and windows that are blocking input to the window that
+    /// owns them until they are closed
+    pub fn is_dialog(self) -> bool {
+        if let Ok(ex_style) = self.get_ex_style() {
+            if ex_style.contains(GwlExStyle::DLGMODALFRAME) {
+                return true;
+            }
+        }
+
+        if let Ok(class) = self.class() {
+            if class == DIALOG_CLASS {
+                return true;
+            }
+        }
+
+        let owner = unsafe { GetWindow(self.hwnd, GW_OWNER) };
+        owner.0 != 0 && !bool::from(unsafe { IsWindowEnabled(owner) })
+    }
+
+    /// Splash screens shown by exes on the splash_exes list while they start
+    /// up, which don't have a caption or can't be resized
+    pub fn is_splash(self, rules: &Rules) -> bool {
+        let style = match self.get_style() {
+            Ok(style) => style,
+            Err(_) => return false,
+        };
+
+        if style.contains(GwlStyle::CAPTION) && style.contains(GwlStyle::THICKFRAME) {
+            return false;
+        }
+
+        rules.splash_exes.contains(&self.exe_name())
+    }
+
+    /// Moves the window to the middle of `area` without resizing it
+    pub fn centre_in(self, area: Rect, insert_after: Option<HWND>) {
+        let rect = self.rect();
+
+        self.set_pos(
+            Rect {
+                x:      area.x + ((area.width - rect.width) / 2),
+                y:      area.y + ((area.height - rect.height) / 2),
+                width:  rect.width,
+                height: rect.height,
+            },
+            insert_after,
+            None,
+        );
     }
 
     pub fn is_active(self) -> bool {
@@ -343,8 +613,12 @@ impl Window {
         let is_cloaked = self.is_cloaked();
         let styles = self.get_style();
         let extended_styles = self.get_ex_style();
+        // Windows belonging to processes we can't query are still managed, they
+        // just can't match any exe-based rules or quirks
+        let exe_name = self.exe_name();
+        let quirks = quirks::of(&exe_name);
 
-        let mut allow_cloaked = false;
+        let mut allow_cloaked = quirks.allow_cloaked;
         if let Some(event) = event {
             if WindowsEventType::Hide == event {
                 allow_cloaked = true
@@ -358,22 +632,20 @@ impl Window {
             (false, false) => {
                 match (styles, extended_styles) {
                     (Ok(style), Ok(ex_style)) => {
-                        if let (Some(title), Ok(path)) = (self.title(), self.exe_path()) {
-                            let exe_name = exe_name_from_path(&path);
-                            let allow_layered = LAYERED_EXE_WHITELIST.contains(&exe_name);
+                        if let Some(title) = self.title() {
+                            let allow_layered = quirks.allow_layered;
 
                             if style.contains(GwlStyle::CAPTION)
                                 && ex_style.contains(GwlExStyle::WINDOWEDGE)
-                                && !ex_style.contains(GwlExStyle::DLGMODALFRAME)
                                 // Get a lot of dupe events coming through that make the redrawing go crazy
                                 // on FocusChange events if I don't filter out this one. But, if we are
-                                // allowing a specific layered window on the whitelist (like Steam), it should
+                                // allowing a specific layered window with a quirk (like Steam), it should
                                 // pass this check
                                 && (allow_layered || !ex_style.contains(GwlExStyle::LAYERED))
                             {
                                 debug!(
                                     "managing {} - {} (styles: {:?}) (extended styles: {:?})",
-                                    exe_name_from_path(&path),
+                                    exe_name,
                                     title,
                                     style,
                                     ex_style
@@ -384,7 +656,7 @@ impl Window {
                                 if let Some(event) = event {
                                     debug!(
                                         "ignoring {} - {} (event: {}) (cloaked: {}) (styles: {:?}) (extended styles: {:?})",
-                                        exe_name_from_path(&path),
+                                        exe_name,
                                         title,
                                         event,
                                         self.is_cloaked(),
@@ -405,10 +677,28 @@ impl Window {
         }
     }
 
+    /// Asks the window for its title, giving up instead of waiting when its
+    /// process isn't responding so that one frozen app can't hold up tiling
     pub fn title(self) -> Option<String> {
         let mut text: [u16; 512] = [0; 512];
-        let len = unsafe { GetWindowTextW(self.hwnd, PWSTR(text.as_mut_ptr()), text.len() as i32) };
-        let text = String::from_utf16_lossy(&text[..len as usize]);
+        let mut len = 0;
+        let sent = unsafe {
+            SendMessageTimeoutW(
+                self.hwnd,
+                WM_GETTEXT,
+                WPARAM(text.len()),
+                LPARAM(text.as_mut_ptr() as isize),
+                SMTO_ABORTIFHUNG,
+                MESSAGE_TIMEOUT_MS,
+                &mut len,
+            )
+        };
+
+        if sent.0 == 0 {
+            return None;
+        }
+
+        let text = String::from_utf16_lossy(&text[..len.min(text.len())]);
 
         if text.is_empty() {
             None
@@ -450,28 +740,111 @@ impl Window {
         }
     }
 
+    /// Asks DWM to draw the window with square corners on Windows 11, or with
+    /// whatever corners it would have otherwise. Earlier versions of Windows
+    /// don't know the attribute and leave the window alone.
+    pub fn set_square_corners(self, square: bool) {
+        // DWMWA_WINDOW_CORNER_PREFERENCE and DWMWCP_DONOTROUND/DWMWCP_DEFAULT,
+        // which are newer than the bindings
+        const CORNER_PREFERENCE: u32 = 33;
+        let preference: u32 = if square { 1 } else { 0 };
+
+        unsafe {
+            let _ = DwmSetWindowAttribute(
+                self.hwnd,
+                CORNER_PREFERENCE,
+                &preference as *const _ as *const _,
+                std::mem::size_of::<u32>() as u32,
+            );
+        }
+    }
+
+    /// Returns false if the window couldn't be moved. Windows of a process that
+    /// isn't responding may still move later on, because the second attempt
+    /// only queues the move for the window's own thread. The z-order is left
+    /// alone without an `insert_after`.
     pub fn set_pos(
         &self,
         rect: Rect,
         insert_after: Option<HWND>,
         flags: Option<SET_WINDOW_POS_FLAGS>,
-    ) {
-        unsafe {
-            SetWindowPos(
+    ) -> bool {
+        let flags = flags.unwrap_or(SWP_NOACTIVATE);
+        let (insert_after, flags) = match insert_after {
+            Some(insert_after) => (insert_after, flags),
+            None => (HWND(0), flags | SWP_NOZORDER),
+        };
+        let set_pos = |flags| unsafe {
+            bool::from(SetWindowPos(
                 self.hwnd,
-                insert_after.unwrap_or(HWND_BOTTOM),
+                insert_after,
                 rect.x,
                 rect.y,
                 rect.width,
                 rect.height,
-                flags.unwrap_or(SWP_NOACTIVATE),
-            );
+                flags,
+            ))
+        };
+
+        if set_pos(flags) || set_pos(flags | SWP_ASYNCWINDOWPOS) {
+            return true;
+        }
+
+        error!(
+            "could not set position of {} ({}): {}",
+            self.exe_name(),
+            self.hwnd.0,
+            std::io::Error::last_os_error()
+        );
+
+        false
+    }
+
+    /// Moves the window up the z-order without moving, resizing or activating
+    /// it
+    pub fn raise(self, insert_after: Option<HWND>) {
+        self.set_pos(
+            Rect::zero(),
+            insert_after,
+            Option::from(SWP_NOMOVE | SWP_NOSIZE | SWP_NOACTIVATE),
+        );
+    }
+
+    /// Moves the mouse cursor onto a point of `rect` within `bounds` where
+    /// this window isn't covered by another one, leaving it alone if it is
+    /// in `rect` already
+    pub fn set_cursor_pos(&self, rect: Rect, bounds: Rect) {
+        let cursor_pos: POINT = unsafe {
+            let mut cursor_pos: POINT = mem::zeroed();
+            GetCursorPos(&mut cursor_pos);
+            cursor_pos
+        };
+
+        if rect.contains_point((cursor_pos.x, cursor_pos.y)) {
+            return;
+        }
+
+        let points = rect.warp_points(bounds);
+        let (x, y) = points
+            .iter()
+            .copied()
+            .find(|point| self.is_at(*point))
+            .unwrap_or(points[0]);
+
+        unsafe {
+            SetCursorPos(x, y);
         }
     }
 
-    pub fn set_cursor_pos(&self, rect: Rect) {
+    /// Whether this window is the one that can be seen at `point`
+    fn is_at(&self, point: (i32, i32)) -> bool {
         unsafe {
-            SetCursorPos(rect.x + (rect.width / 2), rect.y + (rect.height / 2));
+            let hwnd = WindowFromPoint(POINT {
+                x: point.0,
+                y: point.1,
+            });
+
+            GetAncestor(hwnd, GA_ROOT) == self.hwnd
         }
     }
 
@@ -515,15 +888,48 @@ impl Window {
             ShowWindow(self.hwnd, SW_RESTORE);
         };
     }
+
+    pub fn hide(self) {
+        unsafe {
+            ShowWindow(self.hwnd, SW_HIDE);
+        };
+    }
+
+    pub fn minimize(self) {
+        unsafe {
+            ShowWindow(self.hwnd, SW_SHOWMINNOACTIVE);
+        };
+    }
+
+    /// Shows a window that was hidden or minimized without activating it
+    pub fn show(self) {
+        unsafe {
+            ShowWindow(self.hwnd, SW_SHOWNOACTIVATE);
+        };
+    }
+}
+
+/// Which band of the z-order a window belongs in when yatta moves it, see
+/// `Display::insert_after`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layer {
+    Tiled,
+    Floating,
+    /// Windows the user wants in front of everything else yatta manages, like
+    /// zoomed or dragged windows
+    Raised,
 }
 
 impl Default for Window {
     fn default() -> Self {
         Window {
-            hwnd:     HWND(0),
-            hmonitor: HMONITOR(0),
-            tile:     true,
-            resize:   None,
+            hwnd:         HWND(0),
+            hmonitor:     HMONITOR(0),
+            tile:         true,
+            float_rule:   false,
+            resize:       None,
+            hidden:       false,
+            pos_failures: 0,
         }
     }
 }
@@ -558,3 +964,24 @@ impl From<WINDOWINFO> for WindowInfo {
         }
     }
 }
+
+/// Stands in for the Windows API in tests, where none of the handles belong to
+/// a real window. Every window exists and is visible until it is closed.
+#[cfg(test)]
+pub mod mock {
+    use std::{cell::RefCell, collections::HashSet};
+
+    use bindings::Windows::Win32::Foundation::HWND;
+
+    thread_local! {
+        static CLOSED: RefCell<HashSet<isize>> = RefCell::new(HashSet::new());
+    }
+
+    pub fn close(hwnd: HWND) {
+        CLOSED.with(|closed| closed.borrow_mut().insert(hwnd.0));
+    }
+
+    pub fn is_open(hwnd: HWND) -> bool {
+        CLOSED.with(|closed| !closed.borrow().contains(&hwnd.0))
+    }
+}
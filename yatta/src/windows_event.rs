@@ -1,6 +1,8 @@
 use std::{
+    cell::RefCell,
+    collections::HashSet,
     sync::{
-        atomic::{AtomicIsize, Ordering},
+        atomic::{AtomicBool, AtomicIsize, Ordering},
         Arc,
         Mutex,
     },
@@ -11,6 +13,7 @@ use std::{
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use lazy_static::lazy_static;
 use log::{error, info};
+use serde::{Deserialize, Serialize};
 use strum::Display;
 
 use bindings::Windows::Win32::{
@@ -23,6 +26,7 @@ use bindings::Windows::Win32::{
 };
 
 use crate::{
+    config::EventFilter,
     message_loop,
     window::{exe_name_from_path, Window},
     Message,
@@ -32,27 +36,62 @@ use crate::{
 lazy_static! {
     static ref WINDOWS_EVENT_CHANNEL: Arc<Mutex<(Sender<WindowsEvent>, Receiver<WindowsEvent>)>> =
         Arc::new(Mutex::new(unbounded()));
+    /// Windows that a Show event has been sent on for, until they are destroyed
+    static ref SHOWN_WINDOWS: Mutex<HashSet<isize>> = Mutex::new(HashSet::new());
+}
+
+/// Whether name changes of windows that have been shown are sent on, which is
+/// only needed while there are rules that float windows by their title
+static TITLE_RULES: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Only ever read by the handler on the listener thread, which gets a new
+    /// copy whenever the rules change
+    static NAME_CHANGE_ON_LAUNCH_EXES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    /// Set once when the listener thread starts
+    static EVENT_FILTER: RefCell<EventFilter> = RefCell::new(EventFilter::default());
 }
 
 #[derive(Debug, Clone)]
 pub struct WindowsEventListener {
-    hook: Arc<AtomicIsize>,
+    hook:                       Arc<AtomicIsize>,
+    name_change_on_launch_exes: (Sender<Vec<String>>, Receiver<Vec<String>>),
 }
 
 impl Default for WindowsEventListener {
     fn default() -> Self {
         Self {
-            hook: Arc::new(AtomicIsize::new(0)),
+            hook:                       Arc::new(AtomicIsize::new(0)),
+            name_change_on_launch_exes: unbounded(),
         }
     }
 }
 
 impl WindowsEventListener {
-    pub fn start(&self) {
+    /// Hands the listener thread the exes whose name changes are treated as
+    /// windows being shown
+    pub fn set_name_change_on_launch_exes(&self, exes: Vec<String>) {
+        if let Err(error) = self.name_change_on_launch_exes.0.send(exes) {
+            error!(
+                "could not send name change exes to windows event listener: {}",
+                error
+            );
+        }
+    }
+
+    /// Tells the handler whether any rules float windows by their title
+    pub fn set_title_rules(&self, title_rules: bool) {
+        TITLE_RULES.store(title_rules, Ordering::SeqCst);
+    }
+
+    pub fn start(&self, event_filter: EventFilter) {
         let hook = self.hook.clone();
         let yatta_sender = YATTA_CHANNEL.lock().unwrap().0.clone();
+        let name_change_on_launch_exes = self.name_change_on_launch_exes.1.clone();
 
         thread::spawn(move || unsafe {
+            EVENT_FILTER.with(|filter| *filter.borrow_mut() = event_filter);
+
             let hook_ref = SetWinEventHook(
                 EVENT_MIN as u32,
                 EVENT_MAX as u32,
@@ -67,6 +106,10 @@ impl WindowsEventListener {
 
             info!("starting windows event listener");
             message_loop::start(|_| {
+                if let Ok(exes) = name_change_on_launch_exes.try_recv() {
+                    NAME_CHANGE_ON_LAUNCH_EXES.with(|current| *current.borrow_mut() = exes);
+                }
+
                 if let Ok(event) = WINDOWS_EVENT_CHANNEL.lock().unwrap().1.try_recv() {
                     match yatta_sender.send(Message::WindowsEvent(event)) {
                         Ok(_) => {}
@@ -103,11 +146,14 @@ extern "system" fn handler(
     let window = Window {
         hwnd,
         hmonitor,
-        tile: true,
-        resize: None,
+        ..Default::default()
     };
 
     let event_code = unsafe { ::std::mem::transmute(event) };
+    if !EVENT_FILTER.with(|filter| filter.borrow().allows(event_code)) {
+        return;
+    }
+
     let event_type = match WindowsEventType::from_event_code(event_code) {
         Some(event) => event,
         None => {
@@ -117,24 +163,28 @@ extern "system" fn handler(
             //
             // [yatta\src\windows_event.rs:110] event = 32780 ObjectNameChange
             // [yatta\src\windows_event.rs:110] event = 32779 ObjectLocationChange
-            let object_name_change_on_launch = vec!["firefox.exe", "idea64.exe"];
-            if let Ok(path) = window.exe_path() {
-                if event_code == WinEventCode::ObjectNameChange {
-                    if object_name_change_on_launch.contains(&&*exe_name_from_path(&path)) {
-                        WindowsEventType::Show
-                    } else {
-                        return;
-                    }
-                } else {
-                    return;
-                }
+            if event_code == WinEventCode::ObjectNameChange && shows_on_name_change(window) {
+                WindowsEventType::Show
+            } else if event_code == WinEventCode::ObjectNameChange
+                && TITLE_RULES.load(Ordering::SeqCst)
+                && SHOWN_WINDOWS.lock().unwrap().contains(&hwnd.0)
+            {
+                WindowsEventType::TitleChange
             } else {
                 return;
             }
         }
     };
 
+    if event_type == WindowsEventType::Destroy {
+        SHOWN_WINDOWS.lock().unwrap().remove(&hwnd.0);
+    }
+
     if window.should_manage(Option::from(event_type)) {
+        if event_type == WindowsEventType::Show {
+            SHOWN_WINDOWS.lock().unwrap().insert(hwnd.0);
+        }
+
         let event = WindowsEvent {
             event_type,
             event_code,
@@ -151,6 +201,22 @@ extern "system" fn handler(
     }
 }
 
+/// Whether a name change of `window` should be handled as it being shown.
+/// Windows of exes on the name_change_on_launch_exes list always are, any other
+/// window only if it is visible and hasn't been shown yet.
+fn shows_on_name_change(window: Window) -> bool {
+    if let Ok(path) = window.exe_path() {
+        let exe = exe_name_from_path(&path);
+        if NAME_CHANGE_ON_LAUNCH_EXES.with(|exes| exes.borrow().contains(&exe)) {
+            return true;
+        }
+    }
+
+    !SHOWN_WINDOWS.lock().unwrap().contains(&window.hwnd.0)
+        && window.is_visible()
+        && !window.is_minimized()
+}
+
 #[derive(Clone, Copy, Debug, Display, PartialEq)]
 pub enum WindowsEventType {
     Destroy,
@@ -159,6 +225,8 @@ pub enum WindowsEventType {
     Show,
     MoveResizeStart,
     MoveResizeEnd,
+    /// The title of a window that has been shown changed, see `set_title_rules`
+    TitleChange,
 }
 
 impl WindowsEventType {
@@ -190,7 +258,10 @@ pub struct WindowsEvent {
     pub title:      Option<String>,
 }
 
-#[derive(Clone, Copy, FromPrimitive, ToPrimitive, PartialEq, Display, Debug)]
+#[derive(
+    Clone, Copy, FromPrimitive, ToPrimitive, PartialEq, Display, Debug, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
 #[repr(u32)]
 #[allow(dead_code)]
 pub enum WinEventCode {
@@ -1,41 +1,150 @@
-use std::io::Write;
+use std::{
+    fs,
+    io::{self, ErrorKind, Write},
+    iter,
+    path::PathBuf,
+    time::Duration,
+};
 
 use clap::Clap;
+use rustyline::{error::ReadlineError, Editor};
 use uds_windows::UnixStream;
 
-use yatta_core::{CycleDirection, Layout, OperationDirection, ResizeEdge, Sizing, SocketMessage};
+use yatta_core::{
+    state::DesiredState,
+    CycleDirection,
+    Layout,
+    MessageReader,
+    OperationDirection,
+    Query,
+    ResizeEdge,
+    Rules,
+    Sizing,
+    SocketMessage,
+    WireFormat,
+};
 
 #[derive(Clap)]
 #[clap(version = "1.0", author = "Jade I. <jadeiqbal@fastmail.com>")]
 struct Opts {
+    /// Path of the socket yatta is listening on (default: ~/yatta.sock)
+    #[clap(long, parse(from_os_str))]
+    socket: Option<PathBuf>,
+    /// Encoding to use for messages to and from yatta
+    #[clap(long, default_value = "json")]
+    format: WireFormat,
     #[clap(subcommand)]
     subcmd: SubCommand,
 }
 
 #[derive(Clap)]
 enum SubCommand {
-    AdjustGaps(Sizing),
+    // The old gap and padding names still work, but aren't listed any more
+    #[clap(alias = "adjust-gaps")]
+    AdjustInnerGap(AdjustGap),
+    #[clap(alias = "adjust-padding")]
+    AdjustOuterGap(AdjustGap),
     Focus(OperationDirection),
+    /// Focus the next or previous floating window on the focused display
+    FocusFloat(CycleDirection),
     Move(OperationDirection),
+    /// Swap the focused window with the nearest floating window in a direction
+    /// if it is tiled, or the nearest tiled window if it is floating, trading
+    /// tile and floating position
+    SwapWithFloat(OperationDirection),
     Resize(Resize),
     MoveToDisplay(CycleDirection),
     MoveToDisplayNumber(DisplayNumber),
     FocusDisplay(CycleDirection),
     FocusDisplayNumber(DisplayNumber),
     Promote,
-    Retile,
-    GapSize(Gap),
-    PaddingSize(Gap),
-    Layout(Layout),
-    CycleLayout(CycleDirection),
+    /// Hold back moving windows until end-transaction, so that the commands in
+    /// between only move windows once
+    BeginTransaction,
+    EndTransaction,
+    Retile(Retile),
+    #[clap(alias = "gap-size")]
+    InnerGap(Gap),
+    #[clap(alias = "padding-size")]
+    OuterGap(Gap),
+    Layout(SetLayout),
+    CycleLayout(CycleLayout),
+    SetColumns(LaneCount),
+    SetRows(LaneCount),
     ToggleFloat,
+    /// Float every window on the focused display where it is
+    FloatAll,
+    /// Tile the windows on the focused display that were tiled before
+    /// float-all, or every window if float-all wasn't used
+    TileAll,
     TogglePause,
     ToggleMonocle,
+    /// Draw the focused window bigger than its tile, over its neighbours, or
+    /// put it back
+    ToggleZoomFocused,
+    /// Keep the focused window in the first position of its display, with new
+    /// and promoted windows going after it, or stop keeping it there
+    ToggleMaster,
+    /// Swap a window that was in the focused window's position before back
+    /// into it
+    CycleTileOccupants(CycleDirection),
+    SetFloat(Switch),
+    SetPause(Switch),
+    SetMonocle(Switch),
+    Query(Query),
+    /// Print a line of JSON for every focus, display, layout and managed window
+    /// change until interrupted, for status bars to follow
+    Subscribe,
+    /// Keep a connection to yatta open and run commands typed in one after the
+    /// other, exit with Ctrl-D
+    Repl,
     Start,
-    Stop,
+    /// Put back any windows yatta has hidden and exit
+    Stop(Stop),
+    /// Put back any windows yatta has hidden and leave every window alone
+    /// until resumed
+    Suspend,
+    /// Tile the windows that are open after a suspend
+    Resume,
     FloatClass(FloatTarget),
     FloatExe(FloatTarget),
     FloatTitle(FloatTarget),
+    FocusHwnd(Hwnd),
+    FloatHwnd(HwndSwitch),
+    MoveHwndToDisplayNumber(HwndDisplayNumber),
+    Rules(RulesCommand),
+    /// Bring yatta in line with a YAML file describing whether it is paused,
+    /// its rules and the settings of each display, leaving out anything that
+    /// should stay as it is
+    ApplyState(StateFile),
+}
+
+#[derive(Clap)]
+enum RulesCommand {
+    /// Write the float and manage rules yatta is running with as YAML
+    Export,
+    /// Add the rules in a YAML file written by export to the ones yatta is
+    /// running with
+    Import(RulesFile),
+}
+
+#[derive(Clap)]
+struct RulesFile {
+    #[clap(parse(from_os_str))]
+    path: PathBuf,
+}
+
+#[derive(Clap)]
+struct StateFile {
+    #[clap(parse(from_os_str))]
+    path: PathBuf,
+}
+
+#[derive(Clap)]
+struct Stop {
+    /// Kill the yatta process instead, for when it isn't responding
+    #[clap(long)]
+    force: bool,
 }
 
 #[derive(Clap)]
@@ -44,9 +153,59 @@ struct Resize {
     sizing: Sizing,
 }
 
+#[derive(Clap)]
+struct AdjustGap {
+    sizing: Sizing,
+    /// Number of pixels to change the gap by
+    #[clap(long, default_value = "1")]
+    by:     i32,
+}
+
 #[derive(Clap)]
 struct Gap {
-    size: i32,
+    size:    i32,
+    /// Target display number instead of the display under the cursor
+    #[clap(long)]
+    display: Option<usize>,
+}
+
+#[derive(Clap)]
+struct Retile {
+    /// Target display number instead of the display under the cursor
+    #[clap(long)]
+    display: Option<usize>,
+}
+
+#[derive(Clap)]
+struct SetLayout {
+    layout:  Layout,
+    /// Target display number instead of the display under the cursor
+    #[clap(long)]
+    display: Option<usize>,
+}
+
+#[derive(Clap)]
+struct CycleLayout {
+    direction: CycleDirection,
+    /// Target display number instead of the display under the cursor
+    #[clap(long)]
+    display:   Option<usize>,
+}
+
+#[derive(Clap)]
+struct LaneCount {
+    /// Number of columns or rows, or 0 for one per window
+    count:   usize,
+    /// Target display number instead of the display under the cursor
+    #[clap(long)]
+    display: Option<usize>,
+}
+
+#[derive(Clap)]
+struct Switch {
+    /// true or false
+    #[clap(parse(try_from_str))]
+    enabled: bool,
 }
 
 #[derive(Clap)]
@@ -59,101 +218,304 @@ struct FloatTarget {
     id: String,
 }
 
-pub fn send_message(bytes: &[u8]) {
-    let mut socket = dirs::home_dir().unwrap();
-    socket.push("yatta.sock");
-    let socket = socket.as_path();
+#[derive(Clap)]
+struct Hwnd {
+    /// Window handle, as shown by `yattac query state`
+    hwnd: u64,
+}
+
+#[derive(Clap)]
+struct HwndSwitch {
+    /// Window handle, as shown by `yattac query state`
+    hwnd:    u64,
+    /// true or false
+    #[clap(parse(try_from_str))]
+    enabled: bool,
+}
+
+#[derive(Clap)]
+struct HwndDisplayNumber {
+    /// Window handle, as shown by `yattac query state`
+    hwnd:   u64,
+    target: usize,
+}
+
+/// How long to wait for yatta to answer a query
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
 
-    let mut stream = match UnixStream::connect(&socket) {
-        Err(_) => panic!("server is not running"),
+fn connect(socket: &Option<PathBuf>, format: WireFormat) -> MessageReader<UnixStream> {
+    let socket = socket.clone().unwrap_or_else(|| {
+        let mut socket = dirs::home_dir().unwrap();
+        socket.push("yatta.sock");
+        socket
+    });
+
+    let stream = match UnixStream::connect(&socket) {
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            panic!(
+                "yatta is not running, there is no socket at {}",
+                socket.display()
+            )
+        }
+        Err(error) => panic!(
+            "could not connect to yatta at {}: {}. If yatta crashed, the socket is stale and \
+             starting yatta again will clear it",
+            socket.display(),
+            error
+        ),
         Ok(stream) => stream,
     };
 
-    if stream.write_all(&*bytes).is_err() {
-        panic!("couldn't send message")
+    // Rather than hanging if yatta stops answering
+    if let Err(error) = stream.set_read_timeout(Option::from(RESPONSE_TIMEOUT)) {
+        panic!("could not set read timeout on yatta connection: {}", error);
     }
+
+    let mut connection = MessageReader::new(stream);
+
+    // yatta only writes a token when it was started with --require-token
+    let mut token_path = socket.clone().into_os_string();
+    token_path.push(".token");
+    let token_path = PathBuf::from(token_path);
+    match fs::read_to_string(&token_path) {
+        Ok(token) => match send(&mut connection, &SocketMessage::Authenticate(token)) {
+            Ok(Some(_)) => {}
+            _ => panic!("yatta did not accept the token in {}", token_path.display()),
+        },
+        Err(error) if error.kind() == ErrorKind::NotFound => {}
+        Err(error) => panic!(
+            "could not read yatta token from {}: {}",
+            token_path.display(),
+            error
+        ),
+    }
+
+    // Every connection starts out with JSON
+    if format != WireFormat::Json {
+        match send(&mut connection, &SocketMessage::Handshake(format)) {
+            Ok(Some(response)) if response == format.to_string() => connection.format = format,
+            Ok(_) => panic!("couldn't switch to {} wire format", format),
+            Err(error) => panic!("couldn't switch to {} wire format: {}", format, error),
+        }
+    }
+
+    connection
 }
 
-fn main() {
-    let opts: Opts = Opts::parse();
+/// Sends `msg` to yatta, returning the response if it has one
+fn send(
+    connection: &mut MessageReader<UnixStream>,
+    msg: &SocketMessage,
+) -> io::Result<Option<String>> {
+    let bytes = connection.format.encode(msg).unwrap();
+    connection.get_mut().write_all(&bytes)?;
 
-    match opts.subcmd {
-        SubCommand::Focus(direction) => {
-            let bytes = SocketMessage::FocusWindow(direction).as_bytes().unwrap();
-            send_message(&*bytes);
-        }
-        SubCommand::Promote => {
-            let bytes = SocketMessage::Promote.as_bytes().unwrap();
-            send_message(&*bytes);
-        }
-        SubCommand::TogglePause => {
-            let bytes = SocketMessage::TogglePause.as_bytes().unwrap();
-            send_message(&*bytes);
-        }
-        SubCommand::Retile => {
-            let bytes = SocketMessage::Retile.as_bytes().unwrap();
-            send_message(&*bytes);
-        }
-        SubCommand::Move(direction) => {
-            let bytes = SocketMessage::MoveWindow(direction).as_bytes().unwrap();
-            send_message(&*bytes);
-        }
-        SubCommand::Resize(resize) => {
-            let bytes = SocketMessage::ResizeWindow(resize.edge, resize.sizing)
-                .as_bytes()
-                .unwrap();
-            send_message(&*bytes);
-        }
-        SubCommand::MoveToDisplay(direction) => {
-            let bytes = SocketMessage::MoveWindowToDisplay(direction)
-                .as_bytes()
-                .unwrap();
-            send_message(&*bytes);
+    if !msg.has_response() {
+        return Ok(None);
+    }
+
+    // yatta keeps the connection open for more messages after the response
+    let response = match connection.read_frame() {
+        Ok(Some(response)) => response,
+        Ok(None) => {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "yatta closed the connection",
+            ))
         }
-        SubCommand::MoveToDisplayNumber(display_number) => {
-            let bytes = SocketMessage::MoveWindowToDisplayNumber(display_number.target)
-                .as_bytes()
-                .unwrap();
-            send_message(&*bytes);
+        Err(error) if matches!(error.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+            return Err(io::Error::new(
+                ErrorKind::TimedOut,
+                format!("yatta did not answer within {:?}", RESPONSE_TIMEOUT),
+            ))
         }
-        SubCommand::FocusDisplay(direction) => {
-            let bytes = SocketMessage::FocusDisplay(direction).as_bytes().unwrap();
-            send_message(&*bytes);
+        Err(error) => return Err(error),
+    };
+
+    match connection.format.decode::<String>(&response) {
+        Ok(response) => Ok(Option::from(response)),
+        Err(error) => Err(io::Error::new(ErrorKind::InvalidData, error.to_string())),
+    }
+}
+
+fn read_rules(path: &PathBuf) -> io::Result<Rules> {
+    let contents = fs::read_to_string(path)?;
+    serde_yaml::from_str(&contents).map_err(|error| io::Error::new(ErrorKind::InvalidData, error))
+}
+
+fn read_desired_state(path: &PathBuf) -> io::Result<DesiredState> {
+    let contents = fs::read_to_string(path)?;
+    serde_yaml::from_str(&contents).map_err(|error| io::Error::new(ErrorKind::InvalidData, error))
+}
+
+/// The message to send to yatta for `subcmd`, or None for the subcommands that
+/// yattac handles by itself
+fn socket_message(subcmd: SubCommand) -> io::Result<Option<SocketMessage>> {
+    let msg = match subcmd {
+        SubCommand::Focus(direction) => SocketMessage::FocusWindow(direction),
+        SubCommand::FocusFloat(direction) => SocketMessage::FocusFloat(direction),
+        SubCommand::BeginTransaction => SocketMessage::BeginTransaction,
+        SubCommand::EndTransaction => SocketMessage::EndTransaction,
+        SubCommand::Promote => SocketMessage::Promote,
+        SubCommand::TogglePause => SocketMessage::TogglePause,
+        SubCommand::Retile(retile) => SocketMessage::Retile(retile.display),
+        SubCommand::Move(direction) => SocketMessage::MoveWindow(direction),
+        SubCommand::SwapWithFloat(direction) => SocketMessage::SwapWithFloat(direction),
+        SubCommand::Resize(resize) => SocketMessage::ResizeWindow(resize.edge, resize.sizing),
+        SubCommand::MoveToDisplay(direction) => SocketMessage::MoveWindowToDisplay(direction),
+        SubCommand::MoveToDisplayNumber(display_number) => {
+            SocketMessage::MoveWindowToDisplayNumber(display_number.target)
         }
+        SubCommand::FocusDisplay(direction) => SocketMessage::FocusDisplay(direction),
         SubCommand::FocusDisplayNumber(display_number) => {
-            let bytes = SocketMessage::FocusDisplayNumber(display_number.target)
-                .as_bytes()
-                .unwrap();
-            send_message(&*bytes);
+            SocketMessage::FocusDisplayNumber(display_number.target)
         }
-        SubCommand::GapSize(gap) => {
-            let bytes = SocketMessage::GapSize(gap.size).as_bytes().unwrap();
-            send_message(&*bytes);
+        SubCommand::InnerGap(gap) => SocketMessage::SetInnerGap(gap.size, gap.display),
+        SubCommand::OuterGap(gap) => SocketMessage::SetOuterGap(gap.size, gap.display),
+        SubCommand::AdjustInnerGap(gap) => SocketMessage::AdjustInnerGap(gap.sizing, gap.by),
+        SubCommand::AdjustOuterGap(gap) => SocketMessage::AdjustOuterGap(gap.sizing, gap.by),
+        SubCommand::ToggleFloat => SocketMessage::ToggleFloat,
+        SubCommand::FloatAll => SocketMessage::FloatAll,
+        SubCommand::TileAll => SocketMessage::TileAll,
+        SubCommand::ToggleMonocle => SocketMessage::ToggleMonocle,
+        SubCommand::ToggleZoomFocused => SocketMessage::ToggleZoomFocused,
+        SubCommand::ToggleMaster => SocketMessage::ToggleMaster,
+        SubCommand::CycleTileOccupants(direction) => SocketMessage::CycleTileOccupants(direction),
+        SubCommand::Layout(layout) => SocketMessage::Layout(layout.layout, layout.display),
+        SubCommand::CycleLayout(cycle) => {
+            SocketMessage::CycleLayout(cycle.direction, cycle.display)
         }
-        SubCommand::PaddingSize(gap) => {
-            let bytes = SocketMessage::PaddingSize(gap.size).as_bytes().unwrap();
-            send_message(&*bytes);
+        SubCommand::SetColumns(columns) => {
+            SocketMessage::SetColumns(columns.count, columns.display)
         }
-        SubCommand::AdjustGaps(sizing) => {
-            let bytes = SocketMessage::AdjustGaps(sizing).as_bytes().unwrap();
-            send_message(&*bytes);
+        SubCommand::SetRows(rows) => SocketMessage::SetRows(rows.count, rows.display),
+        SubCommand::SetFloat(switch) => SocketMessage::SetFloat(switch.enabled),
+        SubCommand::SetPause(switch) => SocketMessage::SetPause(switch.enabled),
+        SubCommand::SetMonocle(switch) => SocketMessage::SetMonocle(switch.enabled),
+        SubCommand::Query(query) => SocketMessage::Query(query),
+        SubCommand::FloatClass(target) => SocketMessage::FloatClass(target.id),
+        SubCommand::FloatExe(target) => SocketMessage::FloatExe(target.id),
+        SubCommand::FloatTitle(target) => SocketMessage::FloatTitle(target.id),
+        SubCommand::FocusHwnd(target) => SocketMessage::FocusWindowByHwnd(target.hwnd),
+        SubCommand::FloatHwnd(switch) => {
+            SocketMessage::FloatWindowByHwnd(switch.hwnd, switch.enabled)
         }
-        SubCommand::ToggleFloat => {
-            let bytes = SocketMessage::ToggleFloat.as_bytes().unwrap();
-            send_message(&*bytes);
+        SubCommand::MoveHwndToDisplayNumber(target) => {
+            SocketMessage::MoveHwndToDisplayNumber(target.hwnd, target.target)
         }
-        SubCommand::ToggleMonocle => {
-            let bytes = SocketMessage::ToggleMonocle.as_bytes().unwrap();
-            send_message(&*bytes);
+        SubCommand::Rules(RulesCommand::Export) => SocketMessage::Query(Query::Rules),
+        SubCommand::Rules(RulesCommand::Import(file)) => {
+            SocketMessage::ImportRules(read_rules(&file.path)?)
         }
-        SubCommand::Layout(layout) => {
-            let bytes = SocketMessage::Layout(layout).as_bytes().unwrap();
-            send_message(&*bytes);
+        SubCommand::ApplyState(file) => SocketMessage::ApplyState(read_desired_state(&file.path)?),
+        SubCommand::Stop(stop) if !stop.force => SocketMessage::Stop,
+        SubCommand::Suspend => SocketMessage::Suspend,
+        SubCommand::Resume => SocketMessage::Resume,
+        SubCommand::Repl | SubCommand::Subscribe | SubCommand::Start | SubCommand::Stop(_) => {
+            return Ok(None)
         }
-        SubCommand::CycleLayout(direction) => {
-            let bytes = SocketMessage::CycleLayout(direction).as_bytes().unwrap();
-            send_message(&*bytes);
+    };
+
+    Ok(Option::from(msg))
+}
+
+fn subscribe(socket: &Option<PathBuf>, format: WireFormat) {
+    // There can be a long wait between events
+    let mut connection = connect(socket, format).wait_while_idle();
+    if let Err(error) = send(&mut connection, &SocketMessage::Subscribe) {
+        panic!("could not subscribe to yatta events: {}", error);
+    }
+
+    loop {
+        let event = match connection.read_frame() {
+            Ok(Some(event)) => event,
+            Ok(None) => break,
+            Err(error) => panic!("could not read yatta event: {}", error),
+        };
+
+        match connection.format.decode::<String>(&event) {
+            Ok(event) => println!("{}", event),
+            Err(error) => panic!("could not decode yatta event: {}", error),
         }
+    }
+}
+
+fn repl(socket: &Option<PathBuf>, format: WireFormat) {
+    let mut connection = connect(socket, format);
+    let mut editor = Editor::<()>::new();
+
+    let history = dirs::home_dir().map(|mut history| {
+        history.push(".yattac_history");
+        history
+    });
+
+    if let Some(history) = &history {
+        // There is no history the first time round
+        let _ = editor.load_history(history);
+    }
+
+    loop {
+        let line = match editor.readline("yattac> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+            Err(error) => {
+                println!("Error: {}", error);
+                break;
+            }
+        };
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        editor.add_history_entry(line);
+
+        // Each line is parsed like the arguments to a single yattac command
+        let args = iter::once("yattac").chain(line.split_whitespace());
+        let subcmd = match Opts::try_parse_from(args) {
+            Ok(opts) => opts.subcmd,
+            Err(error) => {
+                println!("{}", error);
+                continue;
+            }
+        };
+
+        let msg = match socket_message(subcmd) {
+            Ok(Some(msg)) => msg,
+            Ok(None) => {
+                println!("Error: only commands that are sent to yatta can be used here");
+                continue;
+            }
+            Err(error) => {
+                println!("Error: {}", error);
+                continue;
+            }
+        };
+
+        match send(&mut connection, &msg) {
+            Ok(Some(response)) => println!("{}", response.trim_end()),
+            Ok(None) => {}
+            Err(error) => {
+                println!("Error: {}", error);
+                break;
+            }
+        }
+    }
+
+    if let Some(history) = &history {
+        if let Err(error) = editor.save_history(history) {
+            println!("Error: {}", error);
+        }
+    }
+}
+
+fn main() {
+    let opts: Opts = Opts::parse();
+
+    match opts.subcmd {
+        SubCommand::Repl => repl(&opts.socket, opts.format),
+        SubCommand::Subscribe => subscribe(&opts.socket, opts.format),
         SubCommand::Start => {
             let script = r#"Start-Process yatta -WindowStyle hidden"#;
             match powershell_script::run(script, true) {
@@ -165,7 +527,7 @@ fn main() {
                 }
             }
         }
-        SubCommand::Stop => {
+        SubCommand::Stop(stop) if stop.force => {
             let script = r#"Stop-Process -Name yatta"#;
             match powershell_script::run(script, true) {
                 Ok(output) => {
@@ -176,17 +538,17 @@ fn main() {
                 }
             }
         }
-        SubCommand::FloatClass(target) => {
-            let bytes = SocketMessage::FloatClass(target.id).as_bytes().unwrap();
-            send_message(&*bytes);
-        }
-        SubCommand::FloatExe(target) => {
-            let bytes = SocketMessage::FloatExe(target.id).as_bytes().unwrap();
-            send_message(&*bytes);
-        }
-        SubCommand::FloatTitle(target) => {
-            let bytes = SocketMessage::FloatTitle(target.id).as_bytes().unwrap();
-            send_message(&*bytes);
-        }
+        subcmd => match socket_message(subcmd) {
+            Ok(Some(msg)) => {
+                let mut connection = connect(&opts.socket, opts.format);
+                match send(&mut connection, &msg) {
+                    Ok(Some(response)) => println!("{}", response.trim_end()),
+                    Ok(None) => {}
+                    Err(error) => panic!("couldn't send message: {}", error),
+                }
+            }
+            Ok(None) => {}
+            Err(error) => println!("Error: {}", error),
+        },
     }
 }